@@ -0,0 +1,33 @@
+//! Runs blocking calls on the blocking thread pool of an async runtime.
+
+use std::io::Result;
+
+cfg_async_std! {
+    pub(crate) async fn spawn_async_std<T, F>(task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        async_std::task::spawn_blocking(task).await
+    }
+}
+
+cfg_smol! {
+    pub(crate) async fn spawn_smol<T, F>(task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        smol::unblock(task).await
+    }
+}
+
+cfg_tokio! {
+    pub(crate) async fn spawn_tokio<T, F>(task: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(task).await.map_err(std::io::Error::other)?
+    }
+}
@@ -0,0 +1,61 @@
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// A flag which aborts a cancellable lock method waiting in another thread,
+/// such as `FileExt::lock_exclusive_cancellable`.
+///
+/// Clones of a token share its state, so one can be handed to the thread
+/// which waits for the lock and another kept to cancel it. Once cancelled, a
+/// token stays cancelled.
+#[derive(Clone, Debug, Default)]
+pub struct CancelToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl CancelToken {
+    /// Creates a token which is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the token, waking every lock method waiting with it.
+    pub fn cancel(&self) {
+        *self
+            .inner
+            .cancelled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = true;
+        self.inner.changed.notify_all();
+    }
+
+    /// Returns whether the token was cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self
+            .inner
+            .cancelled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Sleeps for `delay`, or until the token is cancelled. Returns whether it
+    /// was cancelled.
+    pub(crate) fn sleep(&self, delay: Duration) -> bool {
+        let cancelled = self
+            .inner
+            .cancelled
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+        let (cancelled, _) = self
+            .inner
+            .changed
+            .wait_timeout_while(cancelled, delay, |cancelled| !*cancelled)
+            .unwrap_or_else(|e| e.into_inner());
+        *cancelled
+    }
+}
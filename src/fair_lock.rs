@@ -0,0 +1,495 @@
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Result, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use crate::retry::{self, ExponentialBackoff};
+use crate::{lock_contended_error, Exclusive, FileExt, LockKind, LockMode, Shared};
+
+/// A reader-writer lock shared between processes which is granted in the order
+/// it is asked for.
+///
+/// Blocking file locks make no promise of fairness: a steady stream of
+/// overlapping readers keeps a writer waiting forever. A `FairLock` queues
+/// every request in a companion file next to the lock file (the lock file's
+/// path with `.queue` appended), and a request only tries to take the file lock
+/// once every request ahead of it has been granted. Consecutive readers are
+/// granted together, and a reader queued behind a writer waits for the writer.
+/// With [`writer_preference`](FairLock::writer_preference), readers also wait
+/// for writers which asked after them.
+///
+/// Waiting requests poll the queue. A request left in the queue by a process
+/// which no longer exists, or by an earlier process with the same ID, is
+/// dropped from it. The lock file is locked with `FileExt`, so processes which
+/// lock it directly bypass the queue.
+#[derive(Clone, Debug)]
+pub struct FairLock {
+    path: PathBuf,
+    queue_path: PathBuf,
+    writer_preference: bool,
+}
+
+impl FairLock {
+    /// Creates a lock named by the lock file at `path`, without touching the
+    /// filesystem.
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        let path = path.into();
+        let mut queue_path = OsString::from(path.clone());
+        queue_path.push(".queue");
+        Self {
+            path,
+            queue_path: queue_path.into(),
+            writer_preference: false,
+        }
+    }
+
+    /// Sets whether waiting writers are granted the lock before every waiting
+    /// reader, rather than in the order they asked for it. Writers are still
+    /// granted the lock in order among themselves.
+    pub fn writer_preference(mut self, writer_preference: bool) -> Self {
+        self.writer_preference = writer_preference;
+        self
+    }
+
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Locks for reading, blocking until every request ahead of this one has
+    /// been granted and no writer holds the lock.
+    pub fn read(&self) -> Result<FairLockGuard<Shared>> {
+        self.lock()
+    }
+
+    /// Locks for writing, blocking until every request ahead of this one has
+    /// been granted and no reader or writer holds the lock.
+    pub fn write(&self) -> Result<FairLockGuard<Exclusive>> {
+        self.lock()
+    }
+
+    fn lock<M: LockKind>(&self) -> Result<FairLockGuard<M>> {
+        let file = open(&self.path)?;
+        let queue = open(&self.queue_path)?;
+        let (pid, nonce) = (std::process::id(), process_nonce());
+        let ticket = update_queue(&queue, |waiters| {
+            let ticket = waiters.next;
+            waiters.next += 1;
+            waiters.queue.push(Waiter {
+                ticket,
+                mode: M::MODE,
+                pid,
+                nonce,
+            });
+            Ok(ticket)
+        })?;
+        // Withdraws the request if waiting fails, or the thread unwinds, since
+        // requests of a live process are never dropped by others.
+        let mut queued = Queued {
+            queue: &queue,
+            ticket: Some(ticket),
+        };
+
+        let mut policy = ExponentialBackoff::default();
+        retry::lock_until(None, &mut policy, || {
+            update_queue(&queue, |waiters| {
+                waiters.remove_exited(pid, nonce);
+                if !waiters.is_turn(ticket, M::MODE, self.writer_preference) {
+                    return Err(lock_contended_error());
+                }
+                match M::MODE {
+                    LockMode::Shared => FileExt::try_lock_shared(&file)?,
+                    LockMode::Exclusive => FileExt::try_lock_exclusive(&file)?,
+                }
+                waiters.queue.retain(|waiter| waiter.ticket != ticket);
+                Ok(())
+            })
+        })?;
+        queued.ticket = None;
+        Ok(FairLockGuard {
+            file: Some(file),
+            mode: PhantomData,
+        })
+    }
+}
+
+/// A [`FairLock`] held for reading ([`Shared`]) or writing ([`Exclusive`]),
+/// released when the guard is dropped.
+///
+/// Errors from the implicit unlock on drop are ignored; use
+/// [`FairLockGuard::unlock`] to observe them.
+#[derive(Debug)]
+#[must_use = "the lock is released as soon as the guard is dropped"]
+pub struct FairLockGuard<M: LockKind> {
+    /// The locked file, taken out by `unlock`.
+    file: Option<File>,
+    mode: PhantomData<M>,
+}
+
+impl<M: LockKind> FairLockGuard<M> {
+    /// Unlocks the lock, returning any error reported by the operating system.
+    pub fn unlock(mut self) -> Result<()> {
+        self.file
+            .take()
+            .map_or(Ok(()), |file| FileExt::unlock(&file))
+    }
+}
+
+impl<M: LockKind> Drop for FairLockGuard<M> {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            let _ = FileExt::unlock(file);
+        }
+    }
+}
+
+/// A request in the queue, withdrawn when dropped unless it was granted.
+struct Queued<'a> {
+    queue: &'a File,
+    ticket: Option<u64>,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        if let Some(ticket) = self.ticket {
+            let _ = update_queue(self.queue, |waiters| {
+                waiters.queue.retain(|waiter| waiter.ticket != ticket);
+                Ok(())
+            });
+        }
+    }
+}
+
+/// Returns a number identifying the current process among every process which
+/// had, or will have, the same ID.
+fn process_nonce() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+    use std::time::SystemTime;
+
+    static NONCE: OnceLock<u64> = OnceLock::new();
+    *NONCE.get_or_init(|| {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u32(std::process::id());
+        if let Ok(now) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(now.as_nanos());
+        }
+        // Zero marks requests written without a nonce.
+        hasher.finish().max(1)
+    })
+}
+
+/// The contents of the queue file: the next ticket on the first line, then a
+/// line of `<ticket> <r|w> <pid> <nonce>` for every waiting request.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Waiters {
+    next: u64,
+    queue: Vec<Waiter>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Waiter {
+    ticket: u64,
+    mode: LockMode,
+    pid: u32,
+    /// The `process_nonce` of the process which made the request, or zero if
+    /// it is not known.
+    nonce: u64,
+}
+
+impl Waiters {
+    fn parse(contents: &str) -> Self {
+        let mut lines = contents.lines();
+        let next = lines.next().and_then(|line| line.parse().ok()).unwrap_or(0);
+        let queue = lines
+            .filter_map(|line| {
+                let mut fields = line.split(' ');
+                let ticket = fields.next()?.parse().ok()?;
+                let mode = match fields.next()? {
+                    "r" => LockMode::Shared,
+                    "w" => LockMode::Exclusive,
+                    _ => return None,
+                };
+                let pid = fields.next()?.parse().ok()?;
+                let nonce = fields
+                    .next()
+                    .and_then(|nonce| nonce.parse().ok())
+                    .unwrap_or(0);
+                Some(Waiter {
+                    ticket,
+                    mode,
+                    pid,
+                    nonce,
+                })
+            })
+            .collect();
+        Self { next, queue }
+    }
+
+    fn format(&self) -> String {
+        let mut contents = format!("{}\n", self.next);
+        for waiter in &self.queue {
+            let mode = match waiter.mode {
+                LockMode::Shared => "r",
+                LockMode::Exclusive => "w",
+            };
+            contents.push_str(&format!(
+                "{} {} {} {}\n",
+                waiter.ticket, mode, waiter.pid, waiter.nonce
+            ));
+        }
+        contents
+    }
+
+    /// Drops the requests of processes which have exited, including those of
+    /// an earlier process with the ID `pid` of the current one, whose `nonce`
+    /// differs.
+    fn remove_exited(&mut self, pid: u32, nonce: u64) {
+        self.queue.retain(|waiter| {
+            if waiter.pid == pid {
+                waiter.nonce == nonce
+            } else {
+                crate::sys::process_exists(waiter.pid).unwrap_or(true)
+            }
+        });
+    }
+
+    /// Returns whether the request with `ticket` may try to take the lock.
+    fn is_turn(&self, ticket: u64, mode: LockMode, writer_preference: bool) -> bool {
+        let mut ahead = self.queue.iter().filter(|waiter| waiter.ticket < ticket);
+        match (mode, writer_preference) {
+            (LockMode::Exclusive, false) => ahead.next().is_none(),
+            // Readers ahead of a preferred writer wait for it.
+            (LockMode::Exclusive, true) => ahead.all(|waiter| waiter.mode == LockMode::Shared),
+            (LockMode::Shared, false) => ahead.all(|waiter| waiter.mode == LockMode::Shared),
+            (LockMode::Shared, true) => self
+                .queue
+                .iter()
+                .all(|waiter| waiter.mode == LockMode::Shared),
+        }
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// Runs `f` on the waiters in the queue file while holding it locked, and
+/// writes them back, whether `f` succeeds or not.
+fn update_queue<T, F>(queue: &File, f: F) -> Result<T>
+where
+    F: FnOnce(&mut Waiters) -> Result<T>,
+{
+    lock_queue(queue)?;
+    let updated = (|| {
+        let mut contents = String::new();
+        let mut queue = queue;
+        queue.seek(SeekFrom::Start(0))?;
+        queue.read_to_string(&mut contents)?;
+        let mut waiters = Waiters::parse(&contents);
+        let res = f(&mut waiters);
+        let contents = waiters.format();
+        queue.seek(SeekFrom::Start(0))?;
+        queue.write_all(contents.as_bytes())?;
+        queue.set_len(contents.len() as u64)?;
+        res
+    })();
+    let unlocked = unlock_queue(queue);
+    updated.and_then(|res| unlocked.map(|_| res))
+}
+
+/// The queue is always locked with `flock`, so that threads of the same process
+/// exclude each other whatever the default lock backend.
+#[cfg(unix)]
+fn lock_queue(queue: &File) -> Result<()> {
+    queue.locks(crate::LockBackend::Flock).lock_exclusive()
+}
+
+#[cfg(unix)]
+fn unlock_queue(queue: &File) -> Result<()> {
+    queue.locks(crate::LockBackend::Flock).unlock()
+}
+
+#[cfg(windows)]
+fn lock_queue(queue: &File) -> Result<()> {
+    FileExt::lock_exclusive(queue)
+}
+
+#[cfg(windows)]
+fn unlock_queue(queue: &File) -> Result<()> {
+    FileExt::unlock(queue)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use std::process::{Child, Command, Stdio};
+
+    use super::*;
+
+    fn waiter(ticket: u64, mode: LockMode) -> Waiter {
+        Waiter {
+            ticket,
+            mode,
+            pid: 1,
+            nonce: 1,
+        }
+    }
+
+    /// Tests the order in which queued requests take their turn.
+    #[test]
+    fn fair_lock_order() {
+        let waiters = Waiters {
+            next: 5,
+            queue: vec![
+                waiter(1, LockMode::Shared),
+                waiter(2, LockMode::Shared),
+                waiter(3, LockMode::Exclusive),
+                waiter(4, LockMode::Shared),
+            ],
+        };
+        assert_eq!(Waiters::parse(&waiters.format()), waiters);
+
+        assert!(waiters.is_turn(2, LockMode::Shared, false));
+        assert!(!waiters.is_turn(3, LockMode::Exclusive, false));
+        assert!(!waiters.is_turn(4, LockMode::Shared, false));
+
+        // A preferred writer only waits for the readers holding the lock.
+        assert!(waiters.is_turn(3, LockMode::Exclusive, true));
+        assert!(!waiters.is_turn(2, LockMode::Shared, true));
+    }
+
+    /// Tests that requests left behind by an earlier process with the same ID
+    /// are dropped, including those written without a nonce.
+    #[test]
+    fn fair_lock_stale_requests() {
+        let (pid, nonce) = (std::process::id(), process_nonce());
+        let mut waiters = Waiters::parse(&format!(
+            "4\n1 r {pid} {nonce}\n2 w {pid} {}\n3 w {pid}\n",
+            nonce.wrapping_add(1)
+        ));
+        assert_eq!(waiters.queue[2].nonce, 0);
+        waiters.remove_exited(pid, nonce);
+        let tickets: Vec<_> = waiters.queue.iter().map(|waiter| waiter.ticket).collect();
+        assert_eq!(tickets, [1]);
+    }
+
+    const CHILD_ENV: &str = "FS4_FAIR_LOCK_CHILD";
+
+    /// Takes the fair lock at the path named by `CHILD_ENV` for reading or
+    /// writing, and appends `reader` or `writer` to its `.order` file once
+    /// granted. A writer holds the lock until the `.release` file exists. Run
+    /// as a child process by `fair_lock_writer_not_starved`, so that every
+    /// request comes from a different process.
+    #[test]
+    #[ignore]
+    fn fair_lock_child() {
+        let Ok(request) = std::env::var(CHILD_ENV) else {
+            return;
+        };
+        let (path, op) = request.split_once('\n').unwrap();
+        let lock = FairLock::new(path);
+        let sibling = |ext: &str| lock.path().with_extension(ext);
+        let record = |name: &str| {
+            let mut order = OpenOptions::new()
+                .append(true)
+                .create(true)
+                .open(sibling("order"))
+                .unwrap();
+            writeln!(order, "{}", name).unwrap();
+        };
+        match op {
+            "read" => {
+                let _guard = lock.read().unwrap();
+                record("reader");
+            }
+            "write" => {
+                let _guard = lock.write().unwrap();
+                record("writer");
+                while !sibling("release").exists() {
+                    std::thread::sleep(std::time::Duration::from_millis(1));
+                }
+            }
+            _ => panic!("unknown fair lock operation {:?}", op),
+        }
+    }
+
+    /// Takes the fair lock at `path` in a child process (see
+    /// `fair_lock_child`).
+    fn spawn_child(path: &Path, op: &str) -> Child {
+        Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "fair_lock::test::fair_lock_child", "--ignored"])
+            .env(CHILD_ENV, format!("{}\n{}", path.display(), op))
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap()
+    }
+
+    /// Returns the process IDs of the requests in the queue file at `path`.
+    fn queued_pids(path: &Path) -> Vec<u32> {
+        let waiters = Waiters::parse(&fs::read_to_string(path).unwrap_or_default());
+        waiters.queue.iter().map(|waiter| waiter.pid).collect()
+    }
+
+    /// Returns once the queue file at `path` holds a request of `pid`.
+    fn wait_queued(path: &Path, pid: u32) {
+        while !queued_pids(path).contains(&pid) {
+            std::thread::yield_now();
+        }
+    }
+
+    /// Tests that a writer waiting for a reader is granted the lock before a
+    /// reader which asked after it, although that reader could share the lock
+    /// with the first one, and that the request of a writer which exited while
+    /// queued is dropped. Every request comes from a different process.
+    #[test]
+    fn fair_lock_writer_not_starved() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let lock = FairLock::new(tempdir.path().join("fs4.lock"));
+        let order_path = tempdir.path().join("fs4.order");
+
+        let reader = lock.read().unwrap();
+        let mut writer = spawn_child(lock.path(), "write");
+        wait_queued(&lock.queue_path, writer.id());
+        let mut exited = spawn_child(lock.path(), "write");
+        wait_queued(&lock.queue_path, exited.id());
+        exited.kill().unwrap();
+        exited.wait().unwrap();
+        let mut late_reader = spawn_child(lock.path(), "read");
+        wait_queued(&lock.queue_path, late_reader.id());
+
+        // The late reader stays queued while the writer holds the lock, and
+        // the exited writer's request is gone.
+        drop(reader);
+        while fs::read_to_string(&order_path)
+            .unwrap_or_default()
+            .is_empty()
+        {
+            std::thread::yield_now();
+        }
+        assert_eq!(fs::read_to_string(&order_path).unwrap(), "writer\n");
+        assert_eq!(queued_pids(&lock.queue_path), [late_reader.id()]);
+
+        File::create(tempdir.path().join("fs4.release")).unwrap();
+        assert!(writer.wait().unwrap().success());
+        assert!(late_reader.wait().unwrap().success());
+        assert_eq!(fs::read_to_string(&order_path).unwrap(), "writer\nreader\n");
+    }
+}
@@ -1,6 +1,8 @@
 cfg_sync!(
     mod sync_impl;
-    pub use sync_impl::FileExt;
+    pub use sync_impl::{FileExt, FileLockGuard};
+    #[cfg(unix)]
+    pub use sync_impl::FileLocks;
 );
 
 cfg_async!(
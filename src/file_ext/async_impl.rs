@@ -1,13 +1,18 @@
 macro_rules! async_file_ext {
-    ($file: ty, $file_name: literal) => {
+    ($file: ty, $file_name: literal, $sleep: path) => {
         use std::io::Result;
+        use std::time::{Duration, Instant};
+
+        use crate::retry::{self, ExponentialBackoff, RetryPolicy};
+        use crate::{FsStats, LockConversion, LockHolder, LockMode, LockStatus};
 
         #[doc = concat!("Extension trait for `", $file_name, "` which provides allocation, duplication and locking methods.")]
         ///
         /// ## Notes on File Locks
         ///
         /// This library provides whole-file locks in both shared (read) and exclusive
-        /// (read-write) varieties.
+        /// (read-write) varieties, as well as locks on byte ranges of a file (see
+        /// `lock_range`).
         ///
         /// File locks are a cross-platform hazard since the file lock APIs exposed by
         /// operating system kernels vary in subtle and not-so-subtle ways.
@@ -34,19 +39,35 @@ macro_rules! async_file_ext {
             /// Returns the amount of physical space allocated for a file.
             async fn allocated_size(&self) -> Result<u64>;
 
+            /// Returns the stats of the file system holding the file.
+            ///
+            /// See [`FileExt::fs_stats`](crate::FileExt::fs_stats) for how they are
+            /// found. The file system is queried on the runtime's blocking thread pool.
+            async fn fs_stats(&self) -> Result<FsStats>;
+
             /// Ensures that at least `len` bytes of disk space are allocated for the
             /// file, and the file size is at least `len` bytes. After a successful call
             /// to `allocate`, subsequent writes to the file within the specified length
             /// are guaranteed not to fail because of lack of disk space.
             async fn allocate(&self, len: u64) -> Result<()>;
 
-            /// Locks the file for shared usage, blocking if the file is currently
+            /// Locks the file for shared usage, waiting if the file is currently
             /// locked exclusively.
-            fn lock_shared(&self) -> Result<()>;
+            ///
+            /// The file is polled with an [`ExponentialBackoff`] policy while it is
+            /// locked, so the wait neither stalls the executor nor occupies a thread
+            /// of the runtime's blocking pool, but a released lock may go unnoticed
+            /// for up to 100ms. Dropping the returned future leaves the file unlocked.
+            async fn lock_shared(&self) -> Result<()>;
 
-            /// Locks the file for exclusive usage, blocking if the file is currently
+            /// Locks the file for exclusive usage, waiting if the file is currently
             /// locked.
-            fn lock_exclusive(&self) -> Result<()>;
+            ///
+            /// The file is polled with an [`ExponentialBackoff`] policy while it is
+            /// locked, so the wait neither stalls the executor nor occupies a thread
+            /// of the runtime's blocking pool, but a released lock may go unnoticed
+            /// for up to 100ms. Dropping the returned future leaves the file unlocked.
+            async fn lock_exclusive(&self) -> Result<()>;
 
             /// Locks the file for shared usage, or returns a an error if the file is
             /// currently locked (see `lock_contended_error`).
@@ -58,6 +79,108 @@ macro_rules! async_file_ext {
 
             /// Unlocks the file.
             fn unlock(&self) -> Result<()>;
+
+            /// Converts the shared lock held on the file to an exclusive lock,
+            /// waiting while other shared locks are held.
+            ///
+            /// See [`FileExt::upgrade`](crate::FileExt::upgrade) for the semantics of
+            /// the conversion. The conversion is polled like `lock_exclusive`. POSIX
+            /// record and OFD locks keep the shared lock throughout. `flock` and
+            /// Windows release the shared lock on every attempt, and take it back
+            /// without waiting if the exclusive lock is contended, so another
+            /// process may lock the file in between; if the shared lock cannot be
+            /// taken back, the upgrade fails with an error satisfying
+            /// [`is_lock_lost`](crate::is_lock_lost). Either way, if the returned
+            /// future is dropped between attempts, the file is left locked shared.
+            async fn upgrade(&self) -> Result<LockConversion>;
+
+            /// Converts the shared lock held on the file to an exclusive lock, or
+            /// returns an error if other shared locks are held (see
+            /// `lock_contended_error`).
+            fn try_upgrade(&self) -> Result<LockConversion>;
+
+            /// Converts the exclusive lock held on the file to a shared lock (see
+            /// [`FileExt::downgrade`](crate::FileExt::downgrade)).
+            fn downgrade(&self) -> Result<LockConversion>;
+
+            /// Locks `len` bytes of the file starting at `offset`, waiting if any part
+            /// of the range is locked incompatibly. A `len` of zero locks everything
+            /// from `offset` to the end of the file, however far it grows.
+            ///
+            /// See [`FileExt::lock_range`](crate::FileExt::lock_range) for the
+            /// platform-specific semantics of range locks. The range is polled with
+            /// an [`ExponentialBackoff`] policy while it is contended.
+            async fn lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()>;
+
+            /// Locks `len` bytes of the file starting at `offset`, or returns an
+            /// error if any part of the range is locked incompatibly (see
+            /// `lock_contended_error`).
+            fn try_lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()>;
+
+            /// Unlocks `len` bytes of the file starting at `offset`.
+            fn unlock_range(&self, offset: u64, len: u64) -> Result<()>;
+
+            /// Returns a lock which would prevent locking `len` bytes of the file
+            /// starting at `offset` in `mode`, or `None` if the range could be
+            /// locked. Nothing is locked either way.
+            ///
+            /// See [`FileExt::lock_holder`](crate::FileExt::lock_holder) for which
+            /// locks are reported.
+            fn lock_holder(&self, mode: LockMode, offset: u64, len: u64) -> Result<Option<LockHolder>>;
+
+            /// Returns whether the file is locked, and whether the calling process
+            /// holds the lock, without taking a lock.
+            ///
+            /// See [`FileExt::lock_status`](crate::FileExt::lock_status) for how the
+            /// status is queried.
+            fn lock_status(&self) -> Result<LockStatus>;
+
+            /// Locks the file for shared usage, waiting for at most `timeout` if the
+            /// file is currently locked exclusively.
+            ///
+            /// Returns an error of kind `TimedOut` if the lock could not be acquired
+            /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+            async fn lock_shared_timeout(&self, timeout: Duration) -> Result<()>;
+
+            /// Locks the file for exclusive usage, waiting for at most `timeout` if
+            /// the file is currently locked.
+            ///
+            /// Returns an error of kind `TimedOut` if the lock could not be acquired
+            /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+            async fn lock_exclusive_timeout(&self, timeout: Duration) -> Result<()>;
+
+            /// Locks the file for shared usage, waiting until `deadline` at the latest
+            /// if the file is currently locked exclusively.
+            ///
+            /// Returns an error of kind `TimedOut` if the lock could not be acquired
+            /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+            async fn lock_shared_until(&self, deadline: Instant) -> Result<()>;
+
+            /// Locks the file for exclusive usage, waiting until `deadline` at the
+            /// latest if the file is currently locked.
+            ///
+            /// Returns an error of kind `TimedOut` if the lock could not be acquired
+            /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+            async fn lock_exclusive_until(&self, deadline: Instant) -> Result<()>;
+
+            /// Locks the file for shared usage, retrying according to `policy` until
+            /// `deadline` if the file is currently locked exclusively.
+            ///
+            /// Returns an error of kind `TimedOut` if the lock could not be acquired
+            /// in time.
+            async fn lock_shared_with(&self, deadline: Instant, policy: &mut (dyn RetryPolicy + Send)) -> Result<()>;
+
+            /// Locks the file for exclusive usage, retrying according to `policy` until
+            /// `deadline` if the file is currently locked.
+            ///
+            /// Returns an error of kind `TimedOut` if the lock could not be acquired
+            /// in time.
+            async fn lock_exclusive_with(&self, deadline: Instant, policy: &mut (dyn RetryPolicy + Send)) -> Result<()>;
+
+            /// Returns a handle for locking the file with `backend` rather than the
+            /// crate-wide default (see [`FileExt::locks`](crate::FileExt::locks)).
+            #[cfg(unix)]
+            fn locks(&self, backend: crate::LockBackend) -> AsyncFileLocks<'_>;
         }
 
         #[async_trait::async_trait]
@@ -65,14 +188,17 @@ macro_rules! async_file_ext {
             async fn allocated_size(&self) -> Result<u64> {
                 sys::allocated_size(self).await
             }
+            async fn fs_stats(&self) -> Result<FsStats> {
+                sys::fs_stats(self).await
+            }
             async fn allocate(&self, len: u64) -> Result<()> {
                 sys::allocate(self, len).await
             }
-            fn lock_shared(&self) -> Result<()> {
-                sys::lock_shared(self)
+            async fn lock_shared(&self) -> Result<()> {
+                sys::lock_shared(self).await
             }
-            fn lock_exclusive(&self) -> Result<()> {
-                sys::lock_exclusive(self)
+            async fn lock_exclusive(&self) -> Result<()> {
+                sys::lock_exclusive(self).await
             }
             fn try_lock_shared(&self) -> Result<()> {
                 sys::try_lock_shared(self)
@@ -83,6 +209,165 @@ macro_rules! async_file_ext {
             fn unlock(&self) -> Result<()> {
                 sys::unlock(self)
             }
+            async fn upgrade(&self) -> Result<LockConversion> {
+                sys::upgrade(self).await
+            }
+            fn try_upgrade(&self) -> Result<LockConversion> {
+                sys::try_upgrade(self)
+            }
+            fn downgrade(&self) -> Result<LockConversion> {
+                sys::downgrade(self)
+            }
+            async fn lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+                let mut policy = ExponentialBackoff::default();
+                retry::lock_until_async(None, &mut policy, || sys::try_lock_range(self, offset, len, mode), $sleep).await
+            }
+            fn try_lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+                sys::try_lock_range(self, offset, len, mode)
+            }
+            fn unlock_range(&self, offset: u64, len: u64) -> Result<()> {
+                sys::unlock_range(self, offset, len)
+            }
+            fn lock_holder(&self, mode: LockMode, offset: u64, len: u64) -> Result<Option<LockHolder>> {
+                sys::lock_holder(self, mode, offset, len)
+            }
+            fn lock_status(&self) -> Result<LockStatus> {
+                sys::lock_status(self)
+            }
+            async fn lock_shared_timeout(&self, timeout: Duration) -> Result<()> {
+                match Instant::now().checked_add(timeout) {
+                    Some(deadline) => self.lock_shared_until(deadline).await,
+                    None => sys::lock_shared(self).await,
+                }
+            }
+            async fn lock_exclusive_timeout(&self, timeout: Duration) -> Result<()> {
+                match Instant::now().checked_add(timeout) {
+                    Some(deadline) => self.lock_exclusive_until(deadline).await,
+                    None => sys::lock_exclusive(self).await,
+                }
+            }
+            async fn lock_shared_until(&self, deadline: Instant) -> Result<()> {
+                self.lock_shared_with(deadline, &mut ExponentialBackoff::default()).await
+            }
+            async fn lock_exclusive_until(&self, deadline: Instant) -> Result<()> {
+                self.lock_exclusive_with(deadline, &mut ExponentialBackoff::default()).await
+            }
+            async fn lock_shared_with(&self, deadline: Instant, policy: &mut (dyn RetryPolicy + Send)) -> Result<()> {
+                retry::lock_until_async(Some(deadline), policy, || sys::try_lock_shared(self), $sleep).await
+            }
+            async fn lock_exclusive_with(&self, deadline: Instant, policy: &mut (dyn RetryPolicy + Send)) -> Result<()> {
+                retry::lock_until_async(Some(deadline), policy, || sys::try_lock_exclusive(self), $sleep).await
+            }
+            #[cfg(unix)]
+            fn locks(&self, backend: crate::LockBackend) -> AsyncFileLocks<'_> {
+                AsyncFileLocks { file: self, backend }
+            }
+        }
+
+        #[doc = concat!("Whole-file and byte-range locks on a `", $file_name, "`, taken with a specific")]
+        /// [`LockBackend`](crate::LockBackend).
+        ///
+        /// Created by [`AsyncFileExt::locks`]. The lock methods behave like those of
+        /// `AsyncFileExt`, with the semantics of the selected backend (see
+        /// [`FileLocks`](crate::FileLocks)). Waits are polled with an
+        /// [`ExponentialBackoff`] policy.
+        #[cfg(unix)]
+        #[derive(Clone, Copy, Debug)]
+        pub struct AsyncFileLocks<'a> {
+            file: &'a $file,
+            backend: crate::LockBackend,
+        }
+
+        #[cfg(unix)]
+        impl<'a> AsyncFileLocks<'a> {
+            /// Returns the backend the locks are taken with.
+            pub fn backend(&self) -> crate::LockBackend {
+                self.backend
+            }
+
+            /// Locks the file for shared usage, waiting if the file is currently
+            /// locked exclusively.
+            pub async fn lock_shared(&self) -> Result<()> {
+                crate::unix::lock_async(self.fd(), self.backend, LockMode::Shared, $sleep).await
+            }
+
+            /// Locks the file for exclusive usage, waiting if the file is currently
+            /// locked.
+            pub async fn lock_exclusive(&self) -> Result<()> {
+                crate::unix::lock_async(self.fd(), self.backend, LockMode::Exclusive, $sleep).await
+            }
+
+            /// Locks the file for shared usage, or returns an error if the file is
+            /// currently locked exclusively (see `lock_contended_error`).
+            pub fn try_lock_shared(&self) -> Result<()> {
+                crate::unix::lock(self.fd(), self.backend, Some(LockMode::Shared), false)
+            }
+
+            /// Locks the file for exclusive usage, or returns an error if the file is
+            /// currently locked (see `lock_contended_error`).
+            pub fn try_lock_exclusive(&self) -> Result<()> {
+                crate::unix::lock(self.fd(), self.backend, Some(LockMode::Exclusive), false)
+            }
+
+            /// Unlocks the file.
+            pub fn unlock(&self) -> Result<()> {
+                crate::unix::lock(self.fd(), self.backend, None, false)
+            }
+
+            /// Converts the shared lock held on the file to an exclusive lock,
+            /// waiting while other shared locks are held (see
+            /// [`AsyncFileExt::upgrade`]).
+            pub async fn upgrade(&self) -> Result<LockConversion> {
+                crate::unix::upgrade_async(self.fd(), self.backend, $sleep).await
+            }
+
+            /// Converts the shared lock held on the file to an exclusive lock, or
+            /// returns an error if other shared locks are held.
+            pub fn try_upgrade(&self) -> Result<LockConversion> {
+                crate::unix::convert(self.fd(), self.backend, LockMode::Exclusive, false)
+            }
+
+            /// Converts the exclusive lock held on the file to a shared lock.
+            pub fn downgrade(&self) -> Result<LockConversion> {
+                crate::unix::convert(self.fd(), self.backend, LockMode::Shared, false)
+            }
+
+            /// Locks `len` bytes of the file starting at `offset`, waiting if any part
+            /// of the range is locked incompatibly. A `len` of zero locks everything
+            /// from `offset` to the end of the file.
+            pub async fn lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+                let mut policy = ExponentialBackoff::default();
+                retry::lock_until_async(None, &mut policy, || self.try_lock_range(offset, len, mode), $sleep).await
+            }
+
+            /// Locks `len` bytes of the file starting at `offset`, or returns an error
+            /// if any part of the range is locked incompatibly (see
+            /// `lock_contended_error`).
+            pub fn try_lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+                crate::unix::lock_range(self.fd(), self.backend, Some(mode), false, offset, len)
+            }
+
+            /// Unlocks `len` bytes of the file starting at `offset`.
+            pub fn unlock_range(&self, offset: u64, len: u64) -> Result<()> {
+                crate::unix::lock_range(self.fd(), self.backend, None, false, offset, len)
+            }
+
+            /// Returns a lock which would prevent locking `len` bytes of the file
+            /// starting at `offset` in `mode`, or `None` if the range could be
+            /// locked (see [`AsyncFileExt::lock_holder`]).
+            pub fn lock_holder(&self, mode: LockMode, offset: u64, len: u64) -> Result<Option<LockHolder>> {
+                crate::unix::lock_holder(self.fd(), self.backend, mode, offset, len)
+            }
+
+            /// Returns whether the file is locked with the backend, and whether the
+            /// calling process holds the lock (see [`AsyncFileExt::lock_status`]).
+            pub fn lock_status(&self) -> Result<LockStatus> {
+                crate::unix::lock_status(self.fd(), self.backend)
+            }
+
+            fn fd(&self) -> std::os::unix::io::RawFd {
+                std::os::unix::io::AsRawFd::as_raw_fd(self.file)
+            }
         }
     }
 }
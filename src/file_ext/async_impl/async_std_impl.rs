@@ -4,7 +4,7 @@ use crate::unix::async_impl::async_std_impl as sys;
 #[cfg(windows)]
 use crate::windows::async_impl::async_std_impl as sys;
 
-async_file_ext!(File, "async_std::fs::File");
+async_file_ext!(File, "async_std::fs::File", crate::retry::sleep_async_std);
 
 #[cfg(test)]
 mod test {
@@ -12,8 +12,9 @@ mod test {
     extern crate tempdir;
     extern crate test;
 
+    use std::time::{Duration, Instant};
     use async_std::fs;
-    use crate::{allocation_granularity, available_space, async_std::AsyncFileExt, free_space, lock_contended_error, total_space}; 
+    use crate::{allocation_granularity, available_space, async_std::AsyncFileExt, FixedInterval, free_space, lock_contended_error, total_space}; 
 
     /// Tests shared file lock operations.
     #[async_std::test]
@@ -25,8 +26,8 @@ mod test {
         let file3 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
 
         // Concurrent shared access is OK, but not shared and exclusive.
-        file1.lock_shared().unwrap();
-        file2.lock_shared().unwrap();
+        file1.lock_shared().await.unwrap();
+        file2.lock_shared().await.unwrap();
         assert_eq!(file3.try_lock_exclusive().unwrap_err().kind(),
                    lock_contended_error().kind());
         file1.unlock().unwrap();
@@ -35,7 +36,7 @@ mod test {
 
         // Once all shared file locks are dropped, an exclusive lock may be created;
         file2.unlock().unwrap();
-        file3.lock_exclusive().unwrap();
+        file3.lock_exclusive().await.unwrap();
     }
 
     /// Tests exclusive file lock operations.
@@ -47,7 +48,7 @@ mod test {
         let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
 
         // No other access is possible once an exclusive lock is created.
-        file1.lock_exclusive().unwrap();
+        file1.lock_exclusive().await.unwrap();
         assert_eq!(file2.try_lock_exclusive().unwrap_err().kind(),
                    lock_contended_error().kind());
         assert_eq!(file2.try_lock_shared().unwrap_err().kind(),
@@ -55,7 +56,7 @@ mod test {
 
         // Once the exclusive lock is dropped, the second file is able to create a lock.
         file1.unlock().unwrap();
-        file2.lock_exclusive().unwrap();
+        file2.lock_exclusive().await.unwrap();
     }
 
     /// Tests that a lock is released after the file that owns it is dropped.
@@ -66,13 +67,104 @@ mod test {
         let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
         let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
 
-        file1.lock_exclusive().unwrap();
+        file1.lock_exclusive().await.unwrap();
         assert_eq!(file2.try_lock_shared().unwrap_err().kind(),
                    lock_contended_error().kind());
 
         // Drop file1; the lock should be released.
         drop(file1);
-        file2.lock_shared().unwrap();
+        file2.lock_shared().await.unwrap();
+    }
+
+    /// Tests that waiting for a lock does not block the executor.
+    #[async_std::test]
+    async fn lock_wait_yields() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+
+        file1.lock_exclusive().await.unwrap();
+        let unlock = async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            file1.unlock().unwrap();
+        });
+        file2.lock_exclusive().await.unwrap();
+        unlock.await;
+    }
+
+    /// Tests that an abandoned wait does not release a lock taken later.
+    #[async_std::test]
+    async fn lock_cancelled() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file3 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+
+        file1.lock_exclusive().await.unwrap();
+        async_std::future::timeout(Duration::from_millis(50), file2.lock_exclusive()).await.unwrap_err();
+
+        // Nothing keeps waiting on file2's behalf, so a lock it takes again is
+        // not released behind its back.
+        file1.unlock().unwrap();
+        file2.try_lock_exclusive().unwrap();
+        async_std::task::sleep(Duration::from_millis(50)).await;
+        assert_eq!(file3.try_lock_exclusive().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+    }
+
+    /// Tests that an upgrade waits for other shared locks to be released.
+    #[async_std::test]
+    async fn lock_upgrade() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+
+        file1.lock_shared().await.unwrap();
+        file2.lock_shared().await.unwrap();
+        assert_eq!(file1.try_upgrade().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+
+        let unlock = async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            file2.unlock().unwrap();
+            file2
+        });
+        let conversion = file1.upgrade().await;
+        let file2 = unlock.await;
+        assert_eq!(conversion.unwrap(), crate::LockConversion::Reacquired);
+        assert_eq!(file2.try_lock_shared().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+        file1.downgrade().unwrap();
+        file2.try_lock_shared().unwrap();
+    }
+
+    /// Tests that timed lock methods give up once the deadline passes.
+    #[async_std::test]
+    async fn lock_timeout() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+
+        file1.lock_exclusive().await.unwrap();
+        let start = Instant::now();
+        assert_eq!(file2.lock_shared_timeout(Duration::from_millis(50)).await.unwrap_err().kind(),
+                   std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        let mut policy = FixedInterval::new(Duration::from_millis(5));
+        assert_eq!(file2.lock_exclusive_with(Instant::now(), &mut policy).await.unwrap_err().kind(),
+                   std::io::ErrorKind::TimedOut);
+
+        // The lock is acquired once the holder releases it before the deadline.
+        let unlock = async_std::task::spawn(async move {
+            async_std::task::sleep(Duration::from_millis(50)).await;
+            file1.unlock().unwrap();
+        });
+        file2.lock_exclusive_timeout(Duration::from_secs(10)).await.unwrap();
+        unlock.await;
     }
 
     /// Tests file allocation.
@@ -114,4 +206,17 @@ mod test {
         assert!(total_space > available_space);
         assert!(available_space <= free_space);
     }
-}
\ No newline at end of file
+
+    /// Checks that the stats of an open file's file system match those of its
+    /// path.
+    #[async_std::test]
+    async fn fs_stats() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new().write(true).create(true).open(&path).await.unwrap();
+
+        let stats = file.fs_stats().await.unwrap();
+        assert_eq!(stats.total_space(), total_space(&path).unwrap());
+        assert_eq!(stats.fs_type(), crate::filesystem_type(&path).unwrap());
+    }
+}
@@ -4,7 +4,7 @@ use crate::unix::async_impl::tokio_impl as sys;
 #[cfg(windows)]
 use crate::windows::async_impl::tokio_impl as sys;
 
-async_file_ext!(File, "tokio::fs::File");
+async_file_ext!(File, "tokio::fs::File", crate::retry::sleep_tokio);
 
 #[cfg(test)]
 mod test {
@@ -12,21 +12,22 @@ mod test {
     extern crate tempdir;
     extern crate test;
 
+    use std::time::{Duration, Instant};
     use tokio::fs;
-    use crate::{allocation_granularity, available_space, tokio::AsyncFileExt, free_space, lock_contended_error, total_space}; 
+    use crate::{allocation_granularity, available_space, tokio::AsyncFileExt, FixedInterval, free_space, lock_contended_error, total_space}; 
 
     /// Tests shared file lock operations.
     #[tokio::test]
     async fn lock_shared() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
-        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
-        let file3 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file3 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
 
         // Concurrent shared access is OK, but not shared and exclusive.
-        file1.lock_shared().unwrap();
-        file2.lock_shared().unwrap();
+        file1.lock_shared().await.unwrap();
+        file2.lock_shared().await.unwrap();
         assert_eq!(file3.try_lock_exclusive().unwrap_err().kind(),
                    lock_contended_error().kind());
         file1.unlock().unwrap();
@@ -35,7 +36,7 @@ mod test {
 
         // Once all shared file locks are dropped, an exclusive lock may be created;
         file2.unlock().unwrap();
-        file3.lock_exclusive().unwrap();
+        file3.lock_exclusive().await.unwrap();
     }
 
     /// Tests exclusive file lock operations.
@@ -43,11 +44,11 @@ mod test {
     async fn lock_exclusive() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
-        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
 
         // No other access is possible once an exclusive lock is created.
-        file1.lock_exclusive().unwrap();
+        file1.lock_exclusive().await.unwrap();
         assert_eq!(file2.try_lock_exclusive().unwrap_err().kind(),
                    lock_contended_error().kind());
         assert_eq!(file2.try_lock_shared().unwrap_err().kind(),
@@ -55,7 +56,7 @@ mod test {
 
         // Once the exclusive lock is dropped, the second file is able to create a lock.
         file1.unlock().unwrap();
-        file2.lock_exclusive().unwrap();
+        file2.lock_exclusive().await.unwrap();
     }
 
     /// Tests that a lock is released after the file that owns it is dropped.
@@ -63,16 +64,107 @@ mod test {
     async fn lock_cleanup() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
-        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
 
-        file1.lock_exclusive().unwrap();
+        file1.lock_exclusive().await.unwrap();
         assert_eq!(file2.try_lock_shared().unwrap_err().kind(),
                    lock_contended_error().kind());
 
         // Drop file1; the lock should be released.
         drop(file1);
-        file2.lock_shared().unwrap();
+        file2.lock_shared().await.unwrap();
+    }
+
+    /// Tests that waiting for a lock does not block the executor.
+    #[tokio::test]
+    async fn lock_wait_yields() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+
+        // The current-thread runtime can only release file1 while file2 is waiting
+        // if the wait happens off the executor.
+        file1.lock_exclusive().await.unwrap();
+        let unlock = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            file1.unlock().unwrap();
+        };
+        let ((), locked) = tokio::join!(unlock, file2.lock_exclusive());
+        locked.unwrap();
+    }
+
+    /// Tests that an abandoned wait does not release a lock taken later.
+    #[tokio::test]
+    async fn lock_cancelled() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file3 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+
+        file1.lock_exclusive().await.unwrap();
+        tokio::time::timeout(Duration::from_millis(50), file2.lock_exclusive()).await.unwrap_err();
+
+        // Nothing keeps waiting on file2's behalf, so a lock it takes again is
+        // not released behind its back.
+        file1.unlock().unwrap();
+        file2.try_lock_exclusive().unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(file3.try_lock_exclusive().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+    }
+
+    /// Tests that an upgrade waits for other shared locks to be released.
+    #[tokio::test]
+    async fn lock_upgrade() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+
+        file1.lock_shared().await.unwrap();
+        file2.lock_shared().await.unwrap();
+        assert_eq!(file1.try_upgrade().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+
+        let unlock = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            file2.unlock().unwrap();
+        };
+        let (conversion, ()) = tokio::join!(file1.upgrade(), unlock);
+        assert_eq!(conversion.unwrap(), crate::LockConversion::Reacquired);
+        assert_eq!(file2.try_lock_shared().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+        file1.downgrade().unwrap();
+        file2.try_lock_shared().unwrap();
+    }
+
+    /// Tests that timed lock methods give up once the deadline passes.
+    #[tokio::test]
+    async fn lock_timeout() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).await.unwrap();
+
+        file1.lock_exclusive().await.unwrap();
+        let start = Instant::now();
+        assert_eq!(file2.lock_shared_timeout(Duration::from_millis(50)).await.unwrap_err().kind(),
+                   std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        let mut policy = FixedInterval::new(Duration::from_millis(5));
+        assert_eq!(file2.lock_exclusive_with(Instant::now(), &mut policy).await.unwrap_err().kind(),
+                   std::io::ErrorKind::TimedOut);
+
+        // The lock is acquired once the holder releases it before the deadline.
+        let unlock = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            file1.unlock().unwrap();
+        };
+        let (_, locked) = tokio::join!(unlock, file2.lock_exclusive_timeout(Duration::from_secs(10)));
+        locked.unwrap();
     }
 
     /// Tests file allocation.
@@ -80,7 +172,7 @@ mod test {
     async fn allocate() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file = fs::OpenOptions::new().write(true).create(true).open(&path).await.unwrap();
+        let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(&path).await.unwrap();
         let blksize = allocation_granularity(&path).unwrap();
 
         // New files are created with no allocated size.
@@ -114,4 +206,17 @@ mod test {
         assert!(total_space > available_space);
         assert!(available_space <= free_space);
     }
-}
\ No newline at end of file
+
+    /// Checks that the stats of an open file's file system match those of its
+    /// path.
+    #[tokio::test]
+    async fn fs_stats() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(&path).await.unwrap();
+
+        let stats = file.fs_stats().await.unwrap();
+        assert_eq!(stats.total_space(), total_space(&path).unwrap());
+        assert_eq!(stats.fs_type(), crate::filesystem_type(&path).unwrap());
+    }
+}
@@ -1,5 +1,13 @@
 use std::fs::File;
 use std::io::Result;
+use std::mem::ManuallyDrop;
+use std::ops::Deref;
+use std::time::{Duration, Instant};
+
+use crate::retry::{self, ExponentialBackoff, RetryPolicy};
+use crate::{CancelToken, FsStats, LockConversion, LockHolder, LockMode, LockStatus};
+#[cfg(unix)]
+use crate::LockBackend;
 #[cfg(unix)]
 use crate::unix::sync_impl as sys;
 #[cfg(windows)]
@@ -10,7 +18,8 @@ use crate::windows::sync_impl as sys;
 /// ## Notes on File Locks
 ///
 /// This library provides whole-file locks in both shared (read) and exclusive
-/// (read-write) varieties.
+/// (read-write) varieties, as well as locks on byte ranges of a file (see
+/// `lock_range`).
 ///
 /// File locks are a cross-platform hazard since the file lock APIs exposed by
 /// operating system kernels vary in subtle and not-so-subtle ways.
@@ -25,10 +34,16 @@ use crate::windows::sync_impl as sys;
 ///     permissions.
 ///   * File locks may only be relied upon to be advisory.
 ///
+/// Blocking lock methods retry when a signal interrupts them, rather than fail
+/// with an error of kind `Interrupted`. To give up waiting from another thread,
+/// use `lock_shared_cancellable` or `lock_exclusive_cancellable`.
+///
 /// See the tests in `lib.rs` for cross-platform lock behavior that may be
 /// relied upon; see the tests in `unix.rs` and `windows.rs` for examples of
 /// platform-specific behavior. File locks are implemented with
-/// [`flock(2)`](http://man7.org/linux/man-pages/man2/flock.2.html) on Unix and
+/// [`flock(2)`](http://man7.org/linux/man-pages/man2/flock.2.html) on Unix
+/// (or another [`LockBackend`](crate::LockBackend), see
+/// [`set_default_lock_backend`](crate::set_default_lock_backend)) and
 /// [`LockFile`](https://msdn.microsoft.com/en-us/library/windows/desktop/aa365202(v=vs.85).aspx)
 /// on Windows.
 pub trait FileExt { 
@@ -42,6 +57,13 @@ pub trait FileExt {
     /// are guaranteed not to fail because of lack of disk space.
     fn allocate(&self, len: u64) -> Result<()>;
 
+    /// Returns the stats of the file system holding the file.
+    ///
+    /// Unlike `statvfs`, this works for files which were renamed or unlinked
+    /// since they were opened. On Windows, the file system is found through the
+    /// path the file is currently at.
+    fn fs_stats(&self) -> Result<FsStats>;
+
     /// Locks the file for shared usage, blocking if the file is currently
     /// locked exclusively.
     fn lock_shared(&self) -> Result<()>;
@@ -60,6 +82,214 @@ pub trait FileExt {
 
     /// Unlocks the file.
     fn unlock(&self) -> Result<()>;
+
+    /// Converts the shared lock held on the file to an exclusive lock, blocking
+    /// while other shared locks are held.
+    ///
+    /// POSIX record and OFD locks are converted atomically. `flock` and
+    /// Windows can only release the shared lock and take an exclusive one, so
+    /// another process may lock the file in between, which is reported as
+    /// [`LockConversion::Reacquired`]. If the conversion fails, the file is
+    /// still locked shared, unless the shared lock could not be taken again
+    /// either, in which case the error satisfies [`is_lock_lost`](crate::is_lock_lost).
+    fn upgrade(&self) -> Result<LockConversion>;
+
+    /// Converts the shared lock held on the file to an exclusive lock, or
+    /// returns an error if other shared locks are held (see
+    /// `lock_contended_error`). See `upgrade` for the semantics of the
+    /// conversion.
+    fn try_upgrade(&self) -> Result<LockConversion>;
+
+    /// Converts the exclusive lock held on the file to a shared lock.
+    ///
+    /// This is atomic, except with `flock`, which releases the exclusive lock
+    /// before taking the shared one (see `upgrade`).
+    fn downgrade(&self) -> Result<LockConversion>;
+
+    /// Locks `len` bytes of the file starting at `offset`, blocking if any part
+    /// of the range is locked incompatibly. A `len` of zero locks everything
+    /// from `offset` to the end of the file, however far it grows.
+    ///
+    /// Range locks are implemented with POSIX record locks
+    /// ([`fcntl(2)`](http://man7.org/linux/man-pages/man2/fcntl.2.html)) on Unix
+    /// (unless the default [`LockBackend`](crate::LockBackend) is `Ofd`)
+    /// and [`LockFileEx`](https://learn.microsoft.com/en-us/windows/win32/api/fileapi/nf-fileapi-lockfileex)
+    /// on Windows, and their semantics differ:
+    ///
+    ///   * On Unix, range locks belong to the process rather than the `File`:
+    ///     they never conflict with other range locks of the same process,
+    ///     locking a range replaces any lock the process holds on it, and
+    ///     closing *any* descriptor of the file releases all of them. Adjacent
+    ///     and overlapping ranges are merged, and unlocking part of a range
+    ///     splits it.
+    ///   * On Windows, range locks belong to the file handle, conflict with
+    ///     locks held through other handles, and must be unlocked with exactly
+    ///     the `offset` and `len` they were locked with.
+    ///
+    /// Whether range locks interact with the whole-file locks of this trait is
+    /// platform-specific, so the two should not be mixed on the same file.
+    fn lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()>;
+
+    /// Locks `len` bytes of the file starting at `offset`, or returns an error
+    /// if any part of the range is locked incompatibly (see
+    /// `lock_contended_error`).
+    fn try_lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()>;
+
+    /// Unlocks `len` bytes of the file starting at `offset`.
+    fn unlock_range(&self, offset: u64, len: u64) -> Result<()>;
+
+    /// Returns a lock which would prevent locking `len` bytes of the file
+    /// starting at `offset` in `mode`, or `None` if the range could be locked.
+    /// Nothing is locked either way.
+    ///
+    /// This queries record locks with `F_GETLK` (or `F_OFD_GETLK` with the
+    /// `Ofd` backend), which also reports the ID of the process holding a
+    /// POSIX record lock. Locks held by the calling process (or, with the
+    /// `Ofd` backend, through this `File`) are never reported. With the `Flock`
+    /// backend, whole-file `flock` locks are found in `/proc/locks` on Linux,
+    /// with the ID of the process which took them, although on btrfs
+    /// subvolumes they are only found while this `File` holds one itself. On
+    /// other Unix platforms they cannot be queried, and neither can any lock on
+    /// Windows, so the query fails with an error of kind `Unsupported`.
+    fn lock_holder(&self, mode: LockMode, offset: u64, len: u64) -> Result<Option<LockHolder>>;
+
+    /// Returns whether the file is locked, and whether the calling process
+    /// holds the lock, without taking a lock.
+    ///
+    /// On Linux this reads the locks of the default lock backend from
+    /// `/proc/locks`, although on btrfs subvolumes they are only found while
+    /// this `File` holds one itself. Elsewhere on Unix, POSIX record locks are
+    /// queried with `F_GETLK`, which cannot see locks held by the calling
+    /// process, and `flock` locks cannot be queried. On Windows this fails with
+    /// an error of kind `Unsupported`.
+    fn lock_status(&self) -> Result<LockStatus>;
+
+    /// Takes a read lease on the file, which must be open read-only, replacing
+    /// any lease held through it.
+    ///
+    /// A lease is broken when another process opens the file for writing, or
+    /// truncates it: this process is notified (see [`lease`](crate::lease)),
+    /// and the other process waits until the lease is released, for at most
+    /// `/proc/sys/fs/lease-break-time` seconds.
+    ///
+    /// The first lease taken installs a handler for
+    /// [`lease_signal`](crate::lease::lease_signal). If the signal already has
+    /// a handler which this crate did not install, it is left in place, and
+    /// this fails with an error of kind `AlreadyExists`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn set_read_lease(&self) -> Result<()>;
+
+    /// Takes a write lease on the file, which must not be open through any
+    /// other descriptor, replacing any lease held through it.
+    ///
+    /// A write lease is broken when another process opens the file at all,
+    /// and may then be downgraded to a read lease with `set_read_lease`. Like
+    /// `set_read_lease`, this fails with an error of kind `AlreadyExists` if
+    /// another handler is installed for the lease break signal.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn set_write_lease(&self) -> Result<()>;
+
+    /// Releases the lease held through the file, letting a process which
+    /// broke it proceed.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn release_lease(&self) -> Result<()>;
+
+    /// Returns the lease held through the file: `Shared` for a read lease and
+    /// `Exclusive` for a write lease. While a lease is being broken, this
+    /// returns the lease it is being broken to.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn lease(&self) -> Result<Option<LockMode>>;
+
+    /// Locks the file for shared usage, blocking for at most `timeout` if the
+    /// file is currently locked exclusively.
+    ///
+    /// Returns an error of kind `TimedOut` if the lock could not be acquired
+    /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+    fn lock_shared_timeout(&self, timeout: Duration) -> Result<()>;
+
+    /// Locks the file for exclusive usage, blocking for at most `timeout` if
+    /// the file is currently locked.
+    ///
+    /// Returns an error of kind `TimedOut` if the lock could not be acquired
+    /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+    fn lock_exclusive_timeout(&self, timeout: Duration) -> Result<()>;
+
+    /// Locks the file for shared usage, blocking until `deadline` at the latest
+    /// if the file is currently locked exclusively.
+    ///
+    /// Returns an error of kind `TimedOut` if the lock could not be acquired
+    /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+    fn lock_shared_until(&self, deadline: Instant) -> Result<()>;
+
+    /// Locks the file for exclusive usage, blocking until `deadline` at the
+    /// latest if the file is currently locked.
+    ///
+    /// Returns an error of kind `TimedOut` if the lock could not be acquired
+    /// in time. The file is polled with an [`ExponentialBackoff`] policy.
+    fn lock_exclusive_until(&self, deadline: Instant) -> Result<()>;
+
+    /// Locks the file for shared usage, retrying according to `policy` until
+    /// `deadline` if the file is currently locked exclusively.
+    ///
+    /// Returns an error of kind `TimedOut` if the lock could not be acquired
+    /// in time.
+    fn lock_shared_with(&self, deadline: Instant, policy: &mut dyn RetryPolicy) -> Result<()>;
+
+    /// Locks the file for exclusive usage, retrying according to `policy` until
+    /// `deadline` if the file is currently locked.
+    ///
+    /// Returns an error of kind `TimedOut` if the lock could not be acquired
+    /// in time.
+    fn lock_exclusive_with(&self, deadline: Instant, policy: &mut dyn RetryPolicy) -> Result<()>;
+
+    /// Locks the file for shared usage, blocking until the file is no longer
+    /// locked exclusively or `token` is cancelled.
+    ///
+    /// Returns an error of kind `Interrupted`, which [`classify`](crate::classify)
+    /// tells as [`LockError::Cancelled`](crate::LockError::Cancelled), if
+    /// `token` was cancelled first.
+    /// The file is polled with an [`ExponentialBackoff`] policy, and cancelling
+    /// the token wakes the waiting thread.
+    fn lock_shared_cancellable(&self, token: &CancelToken) -> Result<()>;
+
+    /// Locks the file for exclusive usage, blocking until the file is no
+    /// longer locked or `token` is cancelled.
+    ///
+    /// Returns an error of kind `Interrupted`, which [`classify`](crate::classify)
+    /// tells as [`LockError::Cancelled`](crate::LockError::Cancelled), if
+    /// `token` was cancelled first.
+    /// The file is polled with an [`ExponentialBackoff`] policy, and cancelling
+    /// the token wakes the waiting thread.
+    fn lock_exclusive_cancellable(&self, token: &CancelToken) -> Result<()>;
+
+    /// Locks the file for shared usage, blocking if the file is currently
+    /// locked exclusively. The lock is released when the returned guard is
+    /// dropped.
+    fn lock_shared_guard(&self) -> Result<FileLockGuard<'_>>;
+
+    /// Locks the file for exclusive usage, blocking if the file is currently
+    /// locked. The lock is released when the returned guard is dropped.
+    fn lock_exclusive_guard(&self) -> Result<FileLockGuard<'_>>;
+
+    /// Locks the file for shared usage, or returns an error if the file is
+    /// currently locked (see `lock_contended_error`). The lock is released when
+    /// the returned guard is dropped.
+    fn try_lock_shared_guard(&self) -> Result<FileLockGuard<'_>>;
+
+    /// Locks the file for exclusive usage, or returns an error if the file is
+    /// currently locked (see `lock_contended_error`). The lock is released when
+    /// the returned guard is dropped.
+    fn try_lock_exclusive_guard(&self) -> Result<FileLockGuard<'_>>;
+
+    /// Returns a handle for locking the file with `backend` rather than the
+    /// crate-wide default (see [`set_default_lock_backend`](crate::set_default_lock_backend)).
+    ///
+    /// For example, `file.locks(LockBackend::Posix).lock_exclusive()` takes the
+    /// same kind of lock as SQLite, and `file.locks(LockBackend::Ofd)` takes
+    /// open file description locks, which support byte ranges but are owned
+    /// by the `File` rather than the process.
+    #[cfg(unix)]
+    fn locks(&self, backend: LockBackend) -> FileLocks<'_>;
 }
 
 impl FileExt for File {
@@ -69,6 +299,9 @@ impl FileExt for File {
     fn allocate(&self, len: u64) -> Result<()> {
         sys::allocate(self, len)
     }
+    fn fs_stats(&self) -> Result<FsStats> {
+        sys::fs_stats(self)
+    }
     fn lock_shared(&self) -> Result<()> {
         sys::lock_shared(self)
     }
@@ -84,6 +317,229 @@ impl FileExt for File {
     fn unlock(&self) -> Result<()> {
         sys::unlock(self)
     }
+    fn upgrade(&self) -> Result<LockConversion> {
+        sys::upgrade(self)
+    }
+    fn try_upgrade(&self) -> Result<LockConversion> {
+        sys::try_upgrade(self)
+    }
+    fn downgrade(&self) -> Result<LockConversion> {
+        sys::downgrade(self)
+    }
+    fn lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+        sys::lock_range(self, offset, len, mode)
+    }
+    fn try_lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+        sys::try_lock_range(self, offset, len, mode)
+    }
+    fn unlock_range(&self, offset: u64, len: u64) -> Result<()> {
+        sys::unlock_range(self, offset, len)
+    }
+    fn lock_holder(&self, mode: LockMode, offset: u64, len: u64) -> Result<Option<LockHolder>> {
+        sys::lock_holder(self, mode, offset, len)
+    }
+    fn lock_status(&self) -> Result<LockStatus> {
+        sys::lock_status(self)
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn set_read_lease(&self) -> Result<()> {
+        sys::set_lease(self, Some(LockMode::Shared))
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn set_write_lease(&self) -> Result<()> {
+        sys::set_lease(self, Some(LockMode::Exclusive))
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn release_lease(&self) -> Result<()> {
+        sys::set_lease(self, None)
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn lease(&self) -> Result<Option<LockMode>> {
+        sys::lease(self)
+    }
+    fn lock_shared_timeout(&self, timeout: Duration) -> Result<()> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.lock_shared_until(deadline),
+            None => sys::lock_shared(self),
+        }
+    }
+    fn lock_exclusive_timeout(&self, timeout: Duration) -> Result<()> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.lock_exclusive_until(deadline),
+            None => sys::lock_exclusive(self),
+        }
+    }
+    fn lock_shared_until(&self, deadline: Instant) -> Result<()> {
+        self.lock_shared_with(deadline, &mut ExponentialBackoff::default())
+    }
+    fn lock_exclusive_until(&self, deadline: Instant) -> Result<()> {
+        self.lock_exclusive_with(deadline, &mut ExponentialBackoff::default())
+    }
+    fn lock_shared_with(&self, deadline: Instant, policy: &mut dyn RetryPolicy) -> Result<()> {
+        retry::lock_until(Some(deadline), policy, || sys::try_lock_shared(self))
+    }
+    fn lock_exclusive_with(&self, deadline: Instant, policy: &mut dyn RetryPolicy) -> Result<()> {
+        retry::lock_until(Some(deadline), policy, || sys::try_lock_exclusive(self))
+    }
+    fn lock_shared_cancellable(&self, token: &CancelToken) -> Result<()> {
+        retry::lock_cancellable(token, &mut ExponentialBackoff::default(), || sys::try_lock_shared(self))
+    }
+    fn lock_exclusive_cancellable(&self, token: &CancelToken) -> Result<()> {
+        retry::lock_cancellable(token, &mut ExponentialBackoff::default(), || {
+            sys::try_lock_exclusive(self)
+        })
+    }
+    fn lock_shared_guard(&self) -> Result<FileLockGuard<'_>> {
+        sys::lock_shared(self).map(|_| FileLockGuard { file: self })
+    }
+    fn lock_exclusive_guard(&self) -> Result<FileLockGuard<'_>> {
+        sys::lock_exclusive(self).map(|_| FileLockGuard { file: self })
+    }
+    fn try_lock_shared_guard(&self) -> Result<FileLockGuard<'_>> {
+        sys::try_lock_shared(self).map(|_| FileLockGuard { file: self })
+    }
+    fn try_lock_exclusive_guard(&self) -> Result<FileLockGuard<'_>> {
+        sys::try_lock_exclusive(self).map(|_| FileLockGuard { file: self })
+    }
+    #[cfg(unix)]
+    fn locks(&self, backend: LockBackend) -> FileLocks<'_> {
+        FileLocks { file: self, backend }
+    }
+}
+
+/// Whole-file and byte-range locks on a `File`, taken with a specific
+/// [`LockBackend`].
+///
+/// Created by [`FileExt::locks`]. The lock methods behave like those of
+/// `FileExt`, with the semantics of the selected backend. With the `Posix` and
+/// `Ofd` backends, whole-file locks are range locks covering every byte of the
+/// file, however far it grows, and `unlock` releases range locks as well.
+#[cfg(unix)]
+#[derive(Clone, Copy, Debug)]
+pub struct FileLocks<'a> {
+    file: &'a File,
+    backend: LockBackend,
+}
+
+#[cfg(unix)]
+impl<'a> FileLocks<'a> {
+    /// Returns the backend the locks are taken with.
+    pub fn backend(&self) -> LockBackend {
+        self.backend
+    }
+
+    /// Locks the file for shared usage, blocking if the file is currently
+    /// locked exclusively.
+    pub fn lock_shared(&self) -> Result<()> {
+        sys::lock_with(self.file, self.backend, Some(LockMode::Shared), true)
+    }
+
+    /// Locks the file for exclusive usage, blocking if the file is currently
+    /// locked.
+    pub fn lock_exclusive(&self) -> Result<()> {
+        sys::lock_with(self.file, self.backend, Some(LockMode::Exclusive), true)
+    }
+
+    /// Locks the file for shared usage, or returns an error if the file is
+    /// currently locked exclusively (see `lock_contended_error`).
+    pub fn try_lock_shared(&self) -> Result<()> {
+        sys::lock_with(self.file, self.backend, Some(LockMode::Shared), false)
+    }
+
+    /// Locks the file for exclusive usage, or returns an error if the file is
+    /// currently locked (see `lock_contended_error`).
+    pub fn try_lock_exclusive(&self) -> Result<()> {
+        sys::lock_with(self.file, self.backend, Some(LockMode::Exclusive), false)
+    }
+
+    /// Unlocks the file.
+    pub fn unlock(&self) -> Result<()> {
+        sys::lock_with(self.file, self.backend, None, false)
+    }
+
+    /// Converts the shared lock held on the file to an exclusive lock, blocking
+    /// while other shared locks are held (see [`FileExt::upgrade`]).
+    pub fn upgrade(&self) -> Result<LockConversion> {
+        sys::convert_with(self.file, self.backend, LockMode::Exclusive, true)
+    }
+
+    /// Converts the shared lock held on the file to an exclusive lock, or
+    /// returns an error if other shared locks are held (see
+    /// [`FileExt::upgrade`]).
+    pub fn try_upgrade(&self) -> Result<LockConversion> {
+        sys::convert_with(self.file, self.backend, LockMode::Exclusive, false)
+    }
+
+    /// Converts the exclusive lock held on the file to a shared lock (see
+    /// [`FileExt::downgrade`]).
+    pub fn downgrade(&self) -> Result<LockConversion> {
+        sys::convert_with(self.file, self.backend, LockMode::Shared, false)
+    }
+
+    /// Locks `len` bytes of the file starting at `offset`, blocking if any part
+    /// of the range is locked incompatibly. A `len` of zero locks everything
+    /// from `offset` to the end of the file.
+    pub fn lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+        sys::lock_range_with(self.file, self.backend, Some(mode), true, offset, len)
+    }
+
+    /// Locks `len` bytes of the file starting at `offset`, or returns an error
+    /// if any part of the range is locked incompatibly (see
+    /// `lock_contended_error`).
+    pub fn try_lock_range(&self, offset: u64, len: u64, mode: LockMode) -> Result<()> {
+        sys::lock_range_with(self.file, self.backend, Some(mode), false, offset, len)
+    }
+
+    /// Unlocks `len` bytes of the file starting at `offset`.
+    pub fn unlock_range(&self, offset: u64, len: u64) -> Result<()> {
+        sys::lock_range_with(self.file, self.backend, None, false, offset, len)
+    }
+
+    /// Returns a lock which would prevent locking `len` bytes of the file
+    /// starting at `offset` in `mode`, or `None` if the range could be locked
+    /// (see [`FileExt::lock_holder`]).
+    pub fn lock_holder(&self, mode: LockMode, offset: u64, len: u64) -> Result<Option<LockHolder>> {
+        sys::lock_holder_with(self.file, self.backend, mode, offset, len)
+    }
+
+    /// Returns whether the file is locked with the backend, and whether the
+    /// calling process holds the lock (see [`FileExt::lock_status`]).
+    pub fn lock_status(&self) -> Result<LockStatus> {
+        sys::lock_status_with(self.file, self.backend)
+    }
+}
+
+/// A lock held on a `File`, released when the guard is dropped.
+///
+/// Created by the `*_guard` methods of [`FileExt`]. The guard dereferences to
+/// the locked file. Errors from the implicit unlock on drop are ignored; use
+/// [`FileLockGuard::unlock`] to observe them.
+#[derive(Debug)]
+#[must_use = "the lock is released as soon as the guard is dropped"]
+pub struct FileLockGuard<'a> {
+    file: &'a File,
+}
+
+impl<'a> FileLockGuard<'a> {
+    /// Unlocks the file, returning any error reported by the operating system.
+    pub fn unlock(self) -> Result<()> {
+        let guard = ManuallyDrop::new(self);
+        sys::unlock(guard.file)
+    }
+}
+
+impl Deref for FileLockGuard<'_> {
+    type Target = File;
+
+    fn deref(&self) -> &File {
+        self.file
+    }
+}
+
+impl Drop for FileLockGuard<'_> {
+    fn drop(&mut self) {
+        let _ = sys::unlock(self.file);
+    }
 }
 
 #[cfg(test)]
@@ -101,22 +557,22 @@ mod test {
     fn lock_shared() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
-        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
-        let file3 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file3 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
 
         // Concurrent shared access is OK, but not shared and exclusive.
-        file1.lock_shared().unwrap();
-        file2.lock_shared().unwrap();
-        assert_eq!(file3.try_lock_exclusive().unwrap_err().kind(),
+        FileExt::lock_shared(&file1).unwrap();
+        FileExt::lock_shared(&file2).unwrap();
+        assert_eq!(FileExt::try_lock_exclusive(&file3).unwrap_err().kind(),
                    lock_contended_error().kind());
-        file1.unlock().unwrap();
-        assert_eq!(file3.try_lock_exclusive().unwrap_err().kind(),
+        FileExt::unlock(&file1).unwrap();
+        assert_eq!(FileExt::try_lock_exclusive(&file3).unwrap_err().kind(),
                    lock_contended_error().kind());
 
         // Once all shared file locks are dropped, an exclusive lock may be created;
-        file2.unlock().unwrap();
-        file3.lock_exclusive().unwrap();
+        FileExt::unlock(&file2).unwrap();
+        FileExt::lock_exclusive(&file3).unwrap();
     }
 
     /// Tests exclusive file lock operations.
@@ -124,19 +580,19 @@ mod test {
     fn lock_exclusive() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
-        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
 
         // No other access is possible once an exclusive lock is created.
-        file1.lock_exclusive().unwrap();
-        assert_eq!(file2.try_lock_exclusive().unwrap_err().kind(),
+        FileExt::lock_exclusive(&file1).unwrap();
+        assert_eq!(FileExt::try_lock_exclusive(&file2).unwrap_err().kind(),
                    lock_contended_error().kind());
-        assert_eq!(file2.try_lock_shared().unwrap_err().kind(),
+        assert_eq!(FileExt::try_lock_shared(&file2).unwrap_err().kind(),
                    lock_contended_error().kind());
 
         // Once the exclusive lock is dropped, the second file is able to create a lock.
-        file1.unlock().unwrap();
-        file2.lock_exclusive().unwrap();
+        FileExt::unlock(&file1).unwrap();
+        FileExt::lock_exclusive(&file2).unwrap();
     }
 
     /// Tests that a lock is released after the file that owns it is dropped.
@@ -144,16 +600,163 @@ mod test {
     fn lock_cleanup() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
-        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).unwrap();
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
 
-        file1.lock_exclusive().unwrap();
-        assert_eq!(file2.try_lock_shared().unwrap_err().kind(),
+        FileExt::lock_exclusive(&file1).unwrap();
+        assert_eq!(FileExt::try_lock_shared(&file2).unwrap_err().kind(),
                    lock_contended_error().kind());
 
         // Drop file1; the lock should be released.
         drop(file1);
-        file2.lock_shared().unwrap();
+        FileExt::lock_shared(&file2).unwrap();
+    }
+
+    /// Tests that a failed upgrade keeps the shared lock, and that locks can be
+    /// converted in both directions.
+    #[test]
+    fn lock_upgrade_downgrade() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file3 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+
+        FileExt::lock_shared(&file1).unwrap();
+        FileExt::lock_shared(&file2).unwrap();
+        assert_eq!(file1.try_upgrade().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+
+        // The first file is still locked shared after the failed upgrade.
+        FileExt::unlock(&file2).unwrap();
+        assert_eq!(FileExt::try_lock_exclusive(&file3).unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+
+        // Neither `flock` nor `LockFileEx` can convert locks atomically.
+        assert_eq!(file1.try_upgrade().unwrap(), LockConversion::Reacquired);
+        assert_eq!(FileExt::try_lock_shared(&file2).unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+        file1.downgrade().unwrap();
+        FileExt::try_lock_shared(&file2).unwrap();
+        assert_eq!(FileExt::try_lock_exclusive(&file3).unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+    }
+
+    /// Tests that a guard releases its lock when dropped.
+    #[test]
+    fn lock_guard_drop() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+
+        let guard = file1.lock_exclusive_guard().unwrap();
+        assert_eq!(guard.metadata().unwrap().len(), 0);
+        assert_eq!(file2.try_lock_shared_guard().unwrap_err().kind(),
+                   lock_contended_error().kind());
+
+        // Dropping the guard releases the lock, but not the file.
+        drop(guard);
+        let guard = file2.try_lock_shared_guard().unwrap();
+        file1.try_lock_shared_guard().unwrap().unlock().unwrap();
+        assert_eq!(file1.try_lock_exclusive_guard().unwrap_err().kind(),
+                   lock_contended_error().kind());
+
+        // An explicit unlock releases the lock as well.
+        guard.unlock().unwrap();
+        let _guard = file1.try_lock_exclusive_guard().unwrap();
+    }
+
+    /// Tests that timed lock methods give up once the deadline passes.
+    #[test]
+    fn lock_timeout() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+
+        let guard = file1.lock_exclusive_guard().unwrap();
+        let start = Instant::now();
+        assert_eq!(file2.lock_shared_timeout(Duration::from_millis(50)).unwrap_err().kind(),
+                   std::io::ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        assert_eq!(file2.lock_exclusive_until(Instant::now()).unwrap_err().kind(),
+                   std::io::ErrorKind::TimedOut);
+
+        // The lock is acquired once the holder releases it before the deadline.
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                guard.unlock().unwrap();
+            });
+            file2.lock_exclusive_timeout(Duration::from_secs(10)).unwrap();
+        });
+    }
+
+    /// Tests that the retry policy is consulted between attempts.
+    #[test]
+    fn lock_retry_policy() {
+        struct Counting(u32);
+
+        impl RetryPolicy for Counting {
+            fn delay(&mut self, attempt: u32) -> Duration {
+                self.0 = attempt;
+                Duration::from_millis(5)
+            }
+        }
+
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+
+        let mut policy = Counting(0);
+        file2.lock_exclusive_with(Instant::now() + Duration::from_secs(1), &mut policy).unwrap();
+        assert_eq!(policy.0, 0);
+        FileExt::unlock(&file2).unwrap();
+
+        let _guard = file1.lock_shared_guard().unwrap();
+        let deadline = Instant::now() + Duration::from_millis(50);
+        assert_eq!(file2.lock_exclusive_with(deadline, &mut policy).unwrap_err().kind(),
+                   std::io::ErrorKind::TimedOut);
+        assert!(policy.0 > 1);
+        file2.lock_shared_with(deadline, &mut policy).unwrap();
+    }
+
+    /// Tests that a cancellable lock method waits for the lock, and gives up
+    /// as soon as its token is cancelled from another thread.
+    #[test]
+    fn lock_cancellable() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&path).unwrap();
+
+        let guard = file1.lock_exclusive_guard().unwrap();
+        let token = CancelToken::new();
+        std::thread::scope(|s| {
+            let canceller = token.clone();
+            s.spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                canceller.cancel();
+            });
+            let err = file2.lock_exclusive_cancellable(&token).unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::Interrupted);
+            assert!(matches!(crate::classify(&err), crate::LockError::Cancelled));
+        });
+        assert!(token.is_cancelled());
+        assert_eq!(file2.lock_shared_cancellable(&token).unwrap_err().kind(),
+                   std::io::ErrorKind::Interrupted);
+
+        // The lock is acquired once the holder releases it.
+        let token = CancelToken::new();
+        std::thread::scope(|s| {
+            s.spawn(move || {
+                std::thread::sleep(Duration::from_millis(50));
+                guard.unlock().unwrap();
+            });
+            file2.lock_shared_cancellable(&token).unwrap();
+        });
     }
 
     /// Tests file allocation.
@@ -161,7 +764,7 @@ mod test {
     fn allocate() {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file = fs::OpenOptions::new().write(true).create(true).open(&path).unwrap();
+        let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(&path).unwrap();
         let blksize = allocation_granularity(&path).unwrap();
 
         // New files are created with no allocated size.
@@ -196,6 +799,53 @@ mod test {
         assert!(available_space <= free_space);
     }
 
+    /// Checks that the stats of an open file's file system match those of its
+    /// path, even once the file is unlinked.
+    #[test]
+    fn fs_stats() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new().write(true).create(true).truncate(false).open(&path).unwrap();
+
+        let stats = file.fs_stats().unwrap();
+        let path_stats = crate::statvfs(&path).unwrap();
+        assert_eq!(stats.total_space(), path_stats.total_space());
+        assert_eq!(stats.fs_type(), path_stats.fs_type());
+        assert_eq!(stats.fsid(), path_stats.fsid());
+        assert_eq!(stats.allocation_granularity(), path_stats.allocation_granularity());
+        assert_eq!(stats.max_name_len(), path_stats.max_name_len());
+        assert_eq!(stats.is_read_only(), path_stats.is_read_only());
+
+        #[cfg(unix)]
+        {
+            fs::remove_file(&path).unwrap();
+            assert_eq!(file.fs_stats().unwrap().total_space(), path_stats.total_space());
+        }
+    }
+
+    /// Checks filesystem inode counts and flags.
+    #[test]
+    fn filesystem_inodes() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let stats = crate::statvfs(tempdir.path()).unwrap();
+
+        match stats.total_inodes() {
+            Some(total) => {
+                assert!(stats.free_inodes().unwrap() <= total);
+                assert!(stats.available_inodes() <= stats.free_inodes());
+            }
+            None => {
+                assert_eq!(stats.free_inodes(), None);
+                assert_eq!(stats.available_inodes(), None);
+            }
+        }
+        #[cfg(windows)]
+        assert_eq!(stats.total_inodes(), None);
+        assert!(stats.max_name_len() >= 255);
+        assert!(stats.block_size() > 0);
+        assert!(!stats.is_read_only());
+    }
+
     /// Benchmarks creating and removing a file. This is a baseline benchmark
     /// for comparing against the truncate and allocate benchmarks.
     #[bench]
@@ -208,6 +858,7 @@ mod test {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(&path)
                 .unwrap();
             fs::remove_file(&path).unwrap();
@@ -226,6 +877,7 @@ mod test {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(&path)
                 .unwrap();
             file.set_len(size).unwrap();
@@ -245,6 +897,7 @@ mod test {
                 .read(true)
                 .write(true)
                 .create(true)
+                .truncate(false)
                 .open(&path)
                 .unwrap();
             file.allocate(size).unwrap();
@@ -262,6 +915,7 @@ mod test {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(path)
             .unwrap();
         file.allocate(size).unwrap();
@@ -276,11 +930,11 @@ mod test {
     fn bench_lock_unlock(b: &mut test::Bencher) {
         let tempdir = tempdir::TempDir::new("fs4").unwrap();
         let path = tempdir.path().join("fs4");
-        let file = fs::OpenOptions::new().read(true).write(true).create(true).open(path).unwrap();
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path).unwrap();
 
         b.iter(|| {
-            file.lock_exclusive().unwrap();
-            file.unlock().unwrap();
+            FileExt::lock_exclusive(&file).unwrap();
+            FileExt::unlock(&file).unwrap();
         });
     }
 
@@ -1,3 +1,9 @@
+use crate::FsType;
+
+cfg_async!(
+    pub(crate) mod async_impl;
+);
+
 /// `FsStats` contains some common stats about a file system.
 #[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub struct FsStats {
@@ -5,6 +11,15 @@ pub struct FsStats {
     pub(crate) available_space: u64,
     pub(crate) total_space: u64,
     pub(crate) allocation_granularity: u64,
+    pub(crate) fs_type: FsType,
+    pub(crate) total_inodes: Option<u64>,
+    pub(crate) free_inodes: Option<u64>,
+    pub(crate) available_inodes: Option<u64>,
+    pub(crate) max_name_len: u64,
+    pub(crate) fsid: u64,
+    pub(crate) block_size: u64,
+    pub(crate) read_only: bool,
+    pub(crate) nosuid: bool,
 }
 
 impl FsStats {
@@ -34,4 +49,62 @@ impl FsStats {
     pub fn allocation_granularity(&self) -> u64 {
         self.allocation_granularity
     }
+
+    /// Returns the type of the file system (see `filesystem_type`), or
+    /// `FsType::Other` if it can't be determined.
+    pub fn fs_type(&self) -> FsType {
+        self.fs_type
+    }
+
+    /// Returns the total number of inodes in the file system, which limits how
+    /// many files it can hold regardless of free space.
+    ///
+    /// This is `None` for file systems with no such limit, such as btrfs, which
+    /// reports no inodes, and every file system on Windows. The other inode
+    /// counts are `None` for them too.
+    pub fn total_inodes(&self) -> Option<u64> {
+        self.total_inodes
+    }
+
+    /// Returns the number of free inodes in the file system.
+    pub fn free_inodes(&self) -> Option<u64> {
+        self.free_inodes
+    }
+
+    /// Returns the number of inodes available to non-privileged users in the
+    /// file system.
+    pub fn available_inodes(&self) -> Option<u64> {
+        self.available_inodes
+    }
+
+    /// Returns the maximum length of a file name in the file system, in bytes
+    /// on Posix and in UTF-16 code units on Windows.
+    pub fn max_name_len(&self) -> u64 {
+        self.max_name_len
+    }
+
+    /// Returns the ID of the file system: `f_fsid` on Posix, which some
+    /// systems leave zero, and the volume serial number on Windows.
+    pub fn fsid(&self) -> u64 {
+        self.fsid
+    }
+
+    /// Returns the preferred size of I/O on the file system in bytes.
+    ///
+    /// On Posix, this is `f_bsize`, which may differ from the block size
+    /// `allocation_granularity` returns. On Windows, this is the cluster size.
+    pub fn block_size(&self) -> u64 {
+        self.block_size
+    }
+
+    /// Returns whether the file system is mounted read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    /// Returns whether the file system is mounted with set-user-ID and
+    /// set-group-ID bits ignored. This is always false on Windows.
+    pub fn is_nosuid(&self) -> bool {
+        self.nosuid
+    }
 }
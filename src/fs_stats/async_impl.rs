@@ -0,0 +1,71 @@
+macro_rules! async_fs_stats {
+    ($spawn_blocking: path) => {
+        use std::io::Result;
+        use std::path::Path;
+
+        use crate::FsStats;
+
+        /// Get the stats of the file system containing the provided path.
+        ///
+        /// The file system is queried on the runtime's blocking thread pool, as
+        /// `statvfs` may block for a long time, e.g. on an unresponsive NFS mount.
+        pub async fn statvfs<P>(path: P) -> Result<FsStats>
+        where
+            P: AsRef<Path>,
+        {
+            let path = path.as_ref().to_path_buf();
+            $spawn_blocking(move || crate::statvfs(path)).await
+        }
+
+        /// Returns the number of free bytes in the file system containing the provided
+        /// path.
+        pub async fn free_space<P>(path: P) -> Result<u64>
+        where
+            P: AsRef<Path>,
+        {
+            statvfs(path).await.map(|stat| stat.free_space)
+        }
+
+        /// Returns the available space in bytes to non-priveleged users in the file
+        /// system containing the provided path.
+        pub async fn available_space<P>(path: P) -> Result<u64>
+        where
+            P: AsRef<Path>,
+        {
+            statvfs(path).await.map(|stat| stat.available_space)
+        }
+
+        /// Returns the total space in bytes in the file system containing the provided
+        /// path.
+        pub async fn total_space<P>(path: P) -> Result<u64>
+        where
+            P: AsRef<Path>,
+        {
+            statvfs(path).await.map(|stat| stat.total_space)
+        }
+
+        /// Returns the filesystem's disk space allocation granularity in bytes.
+        /// The provided path may be for any file in the filesystem.
+        ///
+        /// On Posix, this is equivalent to the filesystem's block size.
+        /// On Windows, this is equivalent to the filesystem's cluster size.
+        pub async fn allocation_granularity<P>(path: P) -> Result<u64>
+        where
+            P: AsRef<Path>,
+        {
+            statvfs(path).await.map(|stat| stat.allocation_granularity)
+        }
+    };
+}
+
+cfg_async_std! {
+    pub(crate) mod async_std_impl;
+}
+
+cfg_smol! {
+    pub(crate) mod smol_impl;
+}
+
+cfg_tokio! {
+    pub(crate) mod tokio_impl;
+}
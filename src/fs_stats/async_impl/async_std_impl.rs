@@ -0,0 +1,28 @@
+async_fs_stats!(crate::blocking::spawn_async_std);
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use crate::async_std::{
+        allocation_granularity, available_space, free_space, statvfs, total_space,
+    };
+
+    /// Checks filesystem space methods.
+    #[async_std::test]
+    async fn filesystem_space() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let total_space = total_space(tempdir.path()).await.unwrap();
+        let free_space = free_space(tempdir.path()).await.unwrap();
+        let available_space = available_space(tempdir.path()).await.unwrap();
+
+        assert!(total_space > free_space);
+        assert!(total_space > available_space);
+        assert!(available_space <= free_space);
+        assert_eq!(
+            allocation_granularity(tempdir.path()).await.unwrap(),
+            crate::allocation_granularity(tempdir.path()).unwrap()
+        );
+        assert!(statvfs(tempdir.path().join("missing")).await.is_err());
+    }
+}
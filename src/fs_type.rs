@@ -0,0 +1,149 @@
+/// The type of a file system, as reported by `filesystem_type`.
+///
+/// On Linux the type is told by the magic number `statfs(2)` returns, on
+/// other Unix systems and on Windows by the name of the file system.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum FsType {
+    /// ext2, ext3 or ext4, which share a magic number.
+    Ext4,
+    /// XFS.
+    Xfs,
+    /// Btrfs.
+    Btrfs,
+    /// ZFS.
+    Zfs,
+    /// F2FS.
+    F2fs,
+    /// The Unix File System of the BSDs.
+    Ufs,
+    /// APFS.
+    Apfs,
+    /// HFS or HFS+.
+    Hfs,
+    /// NTFS.
+    Ntfs,
+    /// ReFS.
+    Refs,
+    /// FAT12, FAT16 or FAT32.
+    Fat,
+    /// exFAT.
+    Exfat,
+    /// The in-memory tmpfs.
+    Tmpfs,
+    /// The in-memory ramfs.
+    Ramfs,
+    /// SquashFS.
+    Squashfs,
+    /// OverlayFS, as used by container images.
+    Overlay,
+    /// A file system in userspace.
+    Fuse,
+    /// NFS.
+    Nfs,
+    /// SMB, in any of its versions (CIFS, SMB2 and SMB3).
+    Cifs,
+    /// Ceph.
+    Ceph,
+    /// The `/proc` pseudo file system.
+    Proc,
+    /// The `/sys` pseudo file system.
+    Sysfs,
+    /// A file system not listed here.
+    Other,
+}
+
+impl FsType {
+    /// Returns whether the file system is accessed over the network. `flock`
+    /// locks may not be seen by other hosts, or even other processes, on such
+    /// file systems.
+    pub fn is_network(&self) -> bool {
+        matches!(self, FsType::Nfs | FsType::Cifs | FsType::Ceph)
+    }
+
+    /// Returns the type of a file system from the `f_type` magic number of
+    /// `statfs(2)`.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn from_magic(magic: u32) -> Self {
+        match magic {
+            0xef53 => FsType::Ext4,
+            0x5846_5342 => FsType::Xfs,
+            0x9123_683e => FsType::Btrfs,
+            0x2fc1_2fc1 => FsType::Zfs,
+            0xf2f5_2010 => FsType::F2fs,
+            0x5346_544e | 0x7366_746e => FsType::Ntfs,
+            0x4d44 => FsType::Fat,
+            0x2011_bab0 => FsType::Exfat,
+            0x0102_1994 => FsType::Tmpfs,
+            0x8584_58f6 => FsType::Ramfs,
+            0x7371_7368 => FsType::Squashfs,
+            0x794c_7630 => FsType::Overlay,
+            0x6573_5546 => FsType::Fuse,
+            0x6969 => FsType::Nfs,
+            0xff53_4d42 | 0xfe53_4d42 | 0x517b => FsType::Cifs,
+            0x00c3_6400 => FsType::Ceph,
+            0x9fa0 => FsType::Proc,
+            0x6265_6572 => FsType::Sysfs,
+            _ => FsType::Other,
+        }
+    }
+
+    /// Returns the type of a file system from its name, as in `f_fstypename`
+    /// of `statfs(2)` on the BSDs and macOS, or as returned by
+    /// `GetVolumeInformationW` on Windows.
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn from_name(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "ext2fs" | "ext4" => FsType::Ext4,
+            "xfs" => FsType::Xfs,
+            "zfs" => FsType::Zfs,
+            "ufs" | "ffs" => FsType::Ufs,
+            "apfs" => FsType::Apfs,
+            "hfs" => FsType::Hfs,
+            "ntfs" => FsType::Ntfs,
+            "refs" => FsType::Refs,
+            "msdos" | "msdosfs" | "fat" | "fat12" | "fat16" | "fat32" => FsType::Fat,
+            "exfat" => FsType::Exfat,
+            "tmpfs" => FsType::Tmpfs,
+            "fusefs" | "macfuse" | "osxfuse" => FsType::Fuse,
+            "nfs" => FsType::Nfs,
+            "smbfs" | "cifs" => FsType::Cifs,
+            "procfs" => FsType::Proc,
+            _ => FsType::Other,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    use super::*;
+    use crate::{filesystem_type, statvfs};
+
+    /// Tests that the type of a file system is known, and agrees with
+    /// `statvfs`.
+    #[test]
+    fn filesystem_type_of_dir() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let fs_type = filesystem_type(tempdir.path()).unwrap();
+        assert_eq!(statvfs(tempdir.path()).unwrap().fs_type(), fs_type);
+        assert!(filesystem_type(tempdir.path().join("missing")).is_err());
+
+        #[cfg(target_os = "linux")]
+        assert_eq!(filesystem_type("/proc").unwrap(), FsType::Proc);
+    }
+
+    /// Tests the mapping of magic numbers to file system types.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn fs_type_from_magic() {
+        assert_eq!(FsType::from_magic(0xef53), FsType::Ext4);
+        assert_eq!(FsType::from_magic(0x0102_1994), FsType::Tmpfs);
+        assert!(FsType::from_magic(0x6969).is_network());
+        assert!(FsType::from_magic(0xff53_4d42).is_network());
+        assert!(!FsType::from_magic(0x794c_7630).is_network());
+        assert_eq!(FsType::from_magic(1), FsType::Other);
+    }
+}
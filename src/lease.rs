@@ -0,0 +1,325 @@
+//! Notifications of lease breaks, for the file leases taken with
+//! `FileExt::set_read_lease` and `FileExt::set_write_lease`.
+//!
+//! When another process opens a file in a way which conflicts with a lease,
+//! the kernel signals the process holding the lease, and holds the other
+//! process's `open` back until the lease is released or downgraded, or
+//! `/proc/sys/fs/lease-break-time` seconds pass. Taking a lease through this
+//! crate installs a handler for [`lease_signal`] which queues the break, so
+//! that it can be received with [`wait_for_break`] or [`try_wait_for_break`].
+//! An existing handler for the signal is never replaced: if one is installed,
+//! taking a lease, and the functions of this module, fail with an error of
+//! kind `AlreadyExists`.
+//!
+//! In async code, [`break_notifier`] may be registered with the runtime (e.g.
+//! with tokio's `AsyncFd`), and [`try_wait_for_break`] called whenever it
+//! becomes readable. Breaks are queued process-wide, so only one thread or
+//! task should receive them.
+
+use std::io::{Error, ErrorKind, Result};
+use std::mem;
+use std::os::unix::io::{AsRawFd, BorrowedFd, RawFd};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::Mutex;
+
+/// Not exported by `libc` for every Linux target, but the same on all of them.
+const F_SETSIG: libc::c_int = 10;
+
+/// The read and write ends of the pipe the signal handler queues breaks on, or
+/// -1 until the handler is installed.
+static READ_FD: AtomicI32 = AtomicI32::new(-1);
+static WRITE_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// A lease held by this process which another process is breaking.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LeaseBreak {
+    fd: RawFd,
+}
+
+impl LeaseBreak {
+    /// Returns the descriptor of the file whose lease is being broken.
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    /// Returns whether the lease being broken was taken through `file`.
+    pub fn is_for<F: AsRawFd>(&self, file: &F) -> bool {
+        self.fd == file.as_raw_fd()
+    }
+}
+
+/// Returns the signal lease breaks are delivered with, the first real-time
+/// signal. Its disposition must be the default one until the first lease is
+/// taken, which installs the handler of this module.
+pub fn lease_signal() -> libc::c_int {
+    libc::SIGRTMIN()
+}
+
+/// Blocks until a lease held by this process is broken.
+pub fn wait_for_break() -> Result<LeaseBreak> {
+    let fd = install()?;
+    loop {
+        match read_break(fd) {
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            res => return res,
+        }
+    }
+}
+
+/// Returns a lease held by this process which is being broken, or `None` if
+/// no break is queued.
+pub fn try_wait_for_break() -> Result<Option<LeaseBreak>> {
+    let fd = install()?;
+    let mut poll = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    if unsafe { libc::poll(&mut poll, 1, 0) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    if poll.revents & libc::POLLIN == 0 {
+        return Ok(None);
+    }
+    read_break(fd).map(Some)
+}
+
+/// Returns a descriptor which is readable while a lease break is queued.
+pub fn break_notifier() -> Result<BorrowedFd<'static>> {
+    install().map(|fd| unsafe { BorrowedFd::borrow_raw(fd) })
+}
+
+/// Directs the lease break signal for `fd` to the handler, installing it
+/// first if needed.
+pub(crate) fn watch(fd: RawFd) -> Result<()> {
+    install()?;
+    if unsafe { libc::fcntl(fd, F_SETSIG, lease_signal()) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn read_break(fd: RawFd) -> Result<LeaseBreak> {
+    let mut broken: libc::c_int = 0;
+    let size = mem::size_of::<libc::c_int>();
+    match unsafe { libc::read(fd, &mut broken as *mut _ as *mut libc::c_void, size) } {
+        -1 => Err(Error::last_os_error()),
+        // The handler writes whole descriptors, and pipes never split them.
+        _ => Ok(LeaseBreak { fd: broken }),
+    }
+}
+
+/// Creates the pipe and installs the signal handler once, returning the read
+/// end of the pipe. Fails if the signal is already handled, or ignored, by
+/// someone else.
+fn install() -> Result<RawFd> {
+    static INSTALL: Mutex<()> = Mutex::new(());
+
+    let _install = INSTALL.lock().unwrap_or_else(|e| e.into_inner());
+    let fd = READ_FD.load(Ordering::Acquire);
+    if fd != -1 {
+        return Ok(fd);
+    }
+
+    let mut fds = [-1; 2];
+    unsafe {
+        let mut existing: libc::sigaction = mem::zeroed();
+        if libc::sigaction(lease_signal(), std::ptr::null(), &mut existing) == -1 {
+            return Err(Error::last_os_error());
+        }
+        if existing.sa_sigaction != libc::SIG_DFL {
+            return Err(Error::new(
+                ErrorKind::AlreadyExists,
+                "the lease break signal already has a handler",
+            ));
+        }
+        if libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) == -1 {
+            return Err(Error::last_os_error());
+        }
+        // A full pipe drops breaks rather than block the handler.
+        if libc::fcntl(fds[1], libc::F_SETFL, libc::O_NONBLOCK) == -1 {
+            let err = Error::last_os_error();
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            return Err(err);
+        }
+        WRITE_FD.store(fds[1], Ordering::Release);
+
+        let mut action: libc::sigaction = mem::zeroed();
+        action.sa_sigaction = on_lease_break as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_SIGINFO | libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(lease_signal(), &action, std::ptr::null_mut()) == -1 {
+            let err = Error::last_os_error();
+            WRITE_FD.store(-1, Ordering::Release);
+            libc::close(fds[0]);
+            libc::close(fds[1]);
+            return Err(err);
+        }
+    }
+    READ_FD.store(fds[0], Ordering::Release);
+    Ok(fds[0])
+}
+
+/// The start of `siginfo_t` for `SIGPOLL`-style signals, whose fields `libc`
+/// does not expose.
+#[repr(C)]
+struct PollInfo {
+    signo: libc::c_int,
+    errno: libc::c_int,
+    code: libc::c_int,
+    band: libc::c_long,
+    fd: libc::c_int,
+}
+
+extern "C" fn on_lease_break(
+    _signal: libc::c_int,
+    info: *mut libc::siginfo_t,
+    _context: *mut libc::c_void,
+) {
+    unsafe {
+        let fd = (*(info as *const PollInfo)).fd;
+        let errno = *errno_location();
+        libc::write(
+            WRITE_FD.load(Ordering::Acquire),
+            &fd as *const _ as *const libc::c_void,
+            mem::size_of::<libc::c_int>(),
+        );
+        *errno_location() = errno;
+    }
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno_location()
+}
+
+#[cfg(target_os = "android")]
+unsafe fn errno_location() -> *mut libc::c_int {
+    libc::__errno()
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{FileExt, LockMode};
+
+    const CHILD_ENV: &str = "FS4_LEASE_BREAKER";
+
+    /// Opens the file named by `FS4_LEASE_BREAKER` for writing. Run as a child
+    /// process by `lease_break`.
+    #[test]
+    #[ignore]
+    fn lease_breaker() {
+        let Some(path) = std::env::var_os(CHILD_ENV) else {
+            return;
+        };
+        fs::OpenOptions::new().write(true).open(path).unwrap();
+    }
+
+    /// Tests that a read lease is broken by another process opening the file
+    /// for writing, and that the other process waits for it to be released.
+    #[test]
+    fn lease_break() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        fs::write(&path, b"fs4").unwrap();
+        let file = fs::File::open(&path).unwrap();
+        assert_eq!(file.lease().unwrap(), None);
+        file.set_read_lease().unwrap();
+        assert_eq!(file.lease().unwrap(), Some(LockMode::Shared));
+        assert_eq!(try_wait_for_break().unwrap(), None);
+
+        let start = Instant::now();
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "lease::test::lease_breaker", "--ignored"])
+            .env(CHILD_ENV, &path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let broken = wait_for_break().unwrap();
+        assert!(broken.is_for(&file));
+        // A lease being broken reports the type it is being broken to, and
+        // the other process waits until it is released.
+        assert_eq!(file.lease().unwrap(), None);
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(child.try_wait().unwrap().is_none());
+
+        file.release_lease().unwrap();
+        assert!(child.wait().unwrap().success());
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    /// Installs a handler for the lease break signal, and checks that it is
+    /// not replaced. Run as a child process by `lease_signal_taken`, since the
+    /// handler of this module is installed once per process.
+    #[test]
+    #[ignore]
+    fn lease_signal_child() {
+        extern "C" fn ignore(_signal: libc::c_int) {}
+
+        if std::env::var_os(CHILD_ENV).is_none() {
+            return;
+        }
+        let handler = ignore as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        unsafe {
+            assert_ne!(libc::signal(lease_signal(), handler), libc::SIG_ERR);
+        }
+        assert_eq!(
+            try_wait_for_break().unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        fs::write(&path, b"fs4").unwrap();
+        let file = fs::File::open(&path).unwrap();
+        assert_eq!(
+            file.set_read_lease().unwrap_err().kind(),
+            ErrorKind::AlreadyExists
+        );
+        unsafe {
+            assert_eq!(libc::signal(lease_signal(), libc::SIG_DFL), handler);
+        }
+    }
+
+    /// Tests that an existing handler for the lease break signal is left in
+    /// place.
+    #[test]
+    fn lease_signal_taken() {
+        let status = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "lease::test::lease_signal_child", "--ignored"])
+            .env(CHILD_ENV, "")
+            .stdout(Stdio::null())
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+
+    /// Tests that a write lease is only granted on a file which is not open
+    /// elsewhere.
+    #[test]
+    fn write_lease() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        let other = fs::File::open(&path).unwrap();
+        assert!(file.set_write_lease().is_err());
+        drop(other);
+        file.set_write_lease().unwrap();
+        assert_eq!(file.lease().unwrap(), Some(LockMode::Exclusive));
+        file.release_lease().unwrap();
+        assert_eq!(file.lease().unwrap(), None);
+    }
+}
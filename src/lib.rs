@@ -68,36 +68,139 @@ mod windows;
 #[cfg(windows)]
 use windows as sys;
 
+cfg_async!(
+    mod blocking;
+);
+
 mod file_ext;
+#[cfg(all(feature = "sync", unix))]
+pub use file_ext::FileLocks;
 #[cfg(feature = "sync")]
-pub use file_ext::FileExt;
+pub use file_ext::{FileExt, FileLockGuard};
 
 cfg_async_std!(
     pub mod async_std {
         pub use crate::file_ext::async_impl::async_std_impl::AsyncFileExt;
+        #[cfg(unix)]
+        pub use crate::file_ext::async_impl::async_std_impl::AsyncFileLocks;
+        pub use crate::fs_stats::async_impl::async_std_impl::{
+            allocation_granularity, available_space, free_space, statvfs, total_space,
+        };
+        pub use crate::lock_all::async_impl::async_std_impl::{
+            lock_all, try_lock_all, LockAllGuard,
+        };
+        pub use crate::locked_file::async_impl::async_std_impl::LockedFile;
+        pub use crate::named_lock::async_impl::async_std_impl::{NamedRwLock, NamedRwLockGuard};
     }
 );
 
 cfg_smol!(
     pub mod smol {
         pub use crate::file_ext::async_impl::smol_impl::AsyncFileExt;
+        #[cfg(unix)]
+        pub use crate::file_ext::async_impl::smol_impl::AsyncFileLocks;
+        pub use crate::fs_stats::async_impl::smol_impl::{
+            allocation_granularity, available_space, free_space, statvfs, total_space,
+        };
+        pub use crate::lock_all::async_impl::smol_impl::{lock_all, try_lock_all, LockAllGuard};
+        pub use crate::locked_file::async_impl::smol_impl::LockedFile;
+        pub use crate::named_lock::async_impl::smol_impl::{NamedRwLock, NamedRwLockGuard};
     }
 );
 
 cfg_tokio!(
     pub mod tokio {
         pub use crate::file_ext::async_impl::tokio_impl::AsyncFileExt;
+        #[cfg(unix)]
+        pub use crate::file_ext::async_impl::tokio_impl::AsyncFileLocks;
+        pub use crate::fs_stats::async_impl::tokio_impl::{
+            allocation_granularity, available_space, free_space, statvfs, total_space,
+        };
+        pub use crate::lock_all::async_impl::tokio_impl::{lock_all, try_lock_all, LockAllGuard};
+        pub use crate::locked_file::async_impl::tokio_impl::LockedFile;
+        pub use crate::named_lock::async_impl::tokio_impl::{NamedRwLock, NamedRwLockGuard};
     }
 );
 
+cfg_sync!(
+    mod cancel_token;
+    pub use cancel_token::CancelToken;
+);
+
+cfg_sync!(
+    mod pid_file;
+    pub use pid_file::PidFile;
+);
+
+cfg_sync!(
+    mod fair_lock;
+    pub use fair_lock::{FairLock, FairLockGuard};
+);
+
+cfg_sync!(
+    mod process_lock;
+    pub use process_lock::{ProcessLock, ProcessLockGuard};
+);
+
+mod named_lock;
+#[cfg(feature = "sync")]
+pub use named_lock::{NamedRwLock, NamedRwLockGuard};
+
+mod locked_file;
+#[cfg(feature = "sync")]
+pub use locked_file::LockedFile;
+pub use locked_file::{ConversionError, Exclusive, LockKind, Shared};
+
 mod fs_stats;
 pub use fs_stats::FsStats;
 
+mod fs_type;
+pub use fs_type::FsType;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod mount_info;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub use mount_info::{mount_info, mounts, mounts_with, Mount, MountFilter, MountInfo};
+
+#[cfg(unix)]
+mod lock_backend;
+#[cfg(unix)]
+pub use lock_backend::{default_lock_backend, set_default_lock_backend, LockBackend};
+
+mod lock_all;
+#[cfg(feature = "sync")]
+pub use lock_all::{lock_all, try_lock_all, LockAllGuard};
+
+#[cfg(all(feature = "sync", any(target_os = "linux", target_os = "android")))]
+#[cfg_attr(docsrs, doc(cfg(feature = "sync")))]
+pub mod lease;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub mod locks;
+
+mod lock_conversion;
+pub use lock_conversion::{is_lock_lost, LockConversion};
+
+mod lock_error;
+pub use lock_error::{classify, LockError};
+
+mod lock_holder;
+pub use lock_holder::LockHolder;
+
+mod lock_status;
+pub use lock_status::LockStatus;
+
+mod lock_mode;
+pub use lock_mode::LockMode;
+
+mod retry;
+pub use retry::{ExponentialBackoff, FixedInterval, RetryPolicy};
+
 use std::io::{Error, Result};
 use std::path::Path;
 
 /// Returns the error that a call to a try lock method on a contended file will
-/// return.
+/// return. See also [`classify`].
 pub fn lock_contended_error() -> Error {
     sys::lock_error()
 }
@@ -110,6 +213,17 @@ where
     sys::statvfs(path.as_ref())
 }
 
+/// Returns the type of the file system containing the provided path.
+///
+/// `flock` locks on network file systems such as NFS and SMB may not exclude
+/// other hosts, or even other processes (see [`FsType::is_network`]).
+pub fn filesystem_type<P>(path: P) -> Result<FsType>
+where
+    P: AsRef<Path>,
+{
+    sys::filesystem_type(path.as_ref())
+}
+
 /// Returns the number of free bytes in the file system containing the provided
 /// path.
 pub fn free_space<P>(path: P) -> Result<u64>
@@ -0,0 +1,52 @@
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+use crate::LockMode;
+
+cfg_sync!(
+    mod sync_impl;
+    pub use sync_impl::{lock_all, try_lock_all, LockAllGuard};
+);
+
+cfg_async!(
+    pub(crate) mod async_impl;
+);
+
+/// Sorts `files` by file ID, so that every caller locks the same files in the
+/// same order, and merges entries for the same file into one, in exclusive mode
+/// if any of them asked for it.
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+fn lock_order<F>(
+    files: &[(F, LockMode)],
+    file_id: impl Fn(&F) -> std::io::Result<(u64, u64)>,
+) -> std::io::Result<Vec<(F, LockMode)>>
+where
+    F: Copy,
+{
+    let mut order = files
+        .iter()
+        .map(|&(file, mode)| Ok((file_id(&file)?, file, mode)))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    order.sort_by_key(|&(id, _, _)| id);
+    order.dedup_by(|(id, _, mode), (kept_id, _, kept_mode)| {
+        if id != kept_id {
+            return false;
+        }
+        if *mode == LockMode::Exclusive {
+            *kept_mode = LockMode::Exclusive;
+        }
+        true
+    });
+    Ok(order
+        .into_iter()
+        .map(|(_, file, mode)| (file, mode))
+        .collect())
+}
@@ -0,0 +1,102 @@
+macro_rules! async_lock_all {
+    ($file: ty) => {
+        use std::io::Result;
+        use std::mem;
+
+        use crate::LockMode;
+
+        /// Locks every file in `files` in its mode, waiting while any of them is
+        /// locked incompatibly, and returns a guard which unlocks them all.
+        ///
+        /// The files are locked in order of their file ID rather than the order given,
+        /// so that callers locking overlapping sets of files cannot deadlock. A file
+        /// given more than once, through the same or different files, is locked once,
+        /// through the first file in that order, exclusively if any entry asks for an
+        /// exclusive lock. If locking any file fails, the files already locked are
+        /// unlocked again.
+        pub async fn lock_all<'a>(files: &[(&'a $file, LockMode)]) -> Result<LockAllGuard<'a>> {
+            let order = crate::lock_all::lock_order(files, |file| sys::file_id(file))?;
+            // Dropping the guard on error unlocks the files locked so far.
+            let mut guard = LockAllGuard {
+                files: Vec::with_capacity(order.len()),
+            };
+            for (file, mode) in order {
+                match mode {
+                    LockMode::Shared => AsyncFileExt::lock_shared(file).await?,
+                    LockMode::Exclusive => AsyncFileExt::lock_exclusive(file).await?,
+                }
+                guard.files.push(file);
+            }
+            Ok(guard)
+        }
+
+        /// Locks every file in `files` in its mode, or returns an error if any of them
+        /// is locked incompatibly (see `lock_contended_error`), in which case none of
+        /// them remain locked.
+        ///
+        /// See [`lock_all`] for the order in which files are locked.
+        pub fn try_lock_all<'a>(files: &[(&'a $file, LockMode)]) -> Result<LockAllGuard<'a>> {
+            let order = crate::lock_all::lock_order(files, |file| sys::file_id(file))?;
+            let mut guard = LockAllGuard {
+                files: Vec::with_capacity(order.len()),
+            };
+            for (file, mode) in order {
+                match mode {
+                    LockMode::Shared => AsyncFileExt::try_lock_shared(file)?,
+                    LockMode::Exclusive => AsyncFileExt::try_lock_exclusive(file)?,
+                }
+                guard.files.push(file);
+            }
+            Ok(guard)
+        }
+
+        /// Locks held on a set of files by [`lock_all`] or [`try_lock_all`], released
+        /// in reverse order when the guard is dropped.
+        ///
+        /// Errors from the implicit unlock on drop are ignored; use
+        /// [`LockAllGuard::unlock`] to observe them.
+        #[derive(Debug)]
+        #[must_use = "the locks are released as soon as the guard is dropped"]
+        pub struct LockAllGuard<'a> {
+            files: Vec<&'a $file>,
+        }
+
+        impl<'a> LockAllGuard<'a> {
+            /// Returns the locked files, in the order they were locked.
+            pub fn files(&self) -> &[&'a $file] {
+                &self.files
+            }
+
+            /// Unlocks every file, returning the first error reported by the operating
+            /// system. Every file is unlocked even if unlocking another fails.
+            pub fn unlock(mut self) -> Result<()> {
+                let files = mem::take(&mut self.files);
+                files
+                    .into_iter()
+                    .rev()
+                    .map(AsyncFileExt::unlock)
+                    .fold(Ok(()), Result::and)
+            }
+        }
+
+        impl Drop for LockAllGuard<'_> {
+            fn drop(&mut self) {
+                for file in self.files.iter().rev() {
+                    let _ = AsyncFileExt::unlock(*file);
+                }
+            }
+        }
+    };
+}
+
+cfg_async_std! {
+    pub(crate) mod async_std_impl;
+}
+
+cfg_smol! {
+    pub(crate) mod smol_impl;
+}
+
+cfg_tokio! {
+    pub(crate) mod tokio_impl;
+}
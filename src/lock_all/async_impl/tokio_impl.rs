@@ -0,0 +1,54 @@
+use tokio::fs::File;
+
+use crate::file_ext::async_impl::tokio_impl::AsyncFileExt;
+#[cfg(unix)]
+use crate::unix::async_impl::tokio_impl as sys;
+#[cfg(windows)]
+use crate::windows::async_impl::tokio_impl as sys;
+
+async_lock_all!(File);
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use tokio::fs;
+
+    use crate::{
+        lock_contended_error,
+        tokio::{lock_all, try_lock_all, AsyncFileExt},
+        LockMode,
+    };
+
+    /// Tests that a set of files is locked together, and that no file stays
+    /// locked when one of them cannot be locked.
+    #[tokio::test]
+    async fn lock_all_rollback() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let mut options = fs::OpenOptions::new();
+        options.read(true).write(true).create(true);
+        let index = options.open(tempdir.path().join("index")).await.unwrap();
+        let data = options.open(tempdir.path().join("data")).await.unwrap();
+        let other_index = options.open(tempdir.path().join("index")).await.unwrap();
+        let other_data = options.open(tempdir.path().join("data")).await.unwrap();
+
+        let guard = lock_all(&[(&index, LockMode::Shared), (&data, LockMode::Exclusive)])
+            .await
+            .unwrap();
+        assert_eq!(guard.files().len(), 2);
+        assert_eq!(
+            other_data.try_lock_shared().unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        guard.unlock().unwrap();
+
+        // The data file is locked elsewhere, so the index does not stay locked.
+        other_data.try_lock_shared().unwrap();
+        let files = [(&index, LockMode::Exclusive), (&data, LockMode::Exclusive)];
+        assert_eq!(
+            try_lock_all(&files).unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        other_index.try_lock_exclusive().unwrap();
+    }
+}
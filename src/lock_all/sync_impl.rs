@@ -0,0 +1,167 @@
+use std::fs::File;
+use std::io::Result;
+use std::mem;
+
+#[cfg(unix)]
+use crate::unix::sync_impl as sys;
+#[cfg(windows)]
+use crate::windows::sync_impl as sys;
+use crate::{FileExt, LockMode};
+
+/// Locks every file in `files` in its mode, blocking while any of them is
+/// locked incompatibly, and returns a guard which unlocks them all.
+///
+/// The files are locked in order of their file ID rather than the order given,
+/// so that callers locking overlapping sets of files cannot deadlock. A file
+/// given more than once, through the same or different `File`s, is locked once,
+/// through the first `File` in that order, exclusively if any entry asks for an
+/// exclusive lock. If locking any file fails, the files already locked are
+/// unlocked again.
+pub fn lock_all<'a>(files: &[(&'a File, LockMode)]) -> Result<LockAllGuard<'a>> {
+    lock(files, true)
+}
+
+/// Locks every file in `files` in its mode, or returns an error if any of them
+/// is locked incompatibly (see `lock_contended_error`), in which case none of
+/// them remain locked.
+///
+/// See [`lock_all`] for the order in which files are locked.
+pub fn try_lock_all<'a>(files: &[(&'a File, LockMode)]) -> Result<LockAllGuard<'a>> {
+    lock(files, false)
+}
+
+fn lock<'a>(files: &[(&'a File, LockMode)], wait: bool) -> Result<LockAllGuard<'a>> {
+    let order = super::lock_order(files, |file| sys::file_id(file))?;
+    // Dropping the guard on error unlocks the files locked so far.
+    let mut guard = LockAllGuard {
+        files: Vec::with_capacity(order.len()),
+    };
+    for (file, mode) in order {
+        match (mode, wait) {
+            (LockMode::Shared, true) => FileExt::lock_shared(file)?,
+            (LockMode::Exclusive, true) => FileExt::lock_exclusive(file)?,
+            (LockMode::Shared, false) => FileExt::try_lock_shared(file)?,
+            (LockMode::Exclusive, false) => FileExt::try_lock_exclusive(file)?,
+        }
+        guard.files.push(file);
+    }
+    Ok(guard)
+}
+
+/// Locks held on a set of files by [`lock_all`] or [`try_lock_all`], released
+/// in reverse order when the guard is dropped.
+///
+/// Errors from the implicit unlock on drop are ignored; use
+/// [`LockAllGuard::unlock`] to observe them.
+#[derive(Debug)]
+#[must_use = "the locks are released as soon as the guard is dropped"]
+pub struct LockAllGuard<'a> {
+    files: Vec<&'a File>,
+}
+
+impl<'a> LockAllGuard<'a> {
+    /// Returns the locked files, in the order they were locked.
+    pub fn files(&self) -> &[&'a File] {
+        &self.files
+    }
+
+    /// Unlocks every file, returning the first error reported by the operating
+    /// system. Every file is unlocked even if unlocking another fails.
+    pub fn unlock(mut self) -> Result<()> {
+        let files = mem::take(&mut self.files);
+        files
+            .into_iter()
+            .rev()
+            .map(FileExt::unlock)
+            .fold(Ok(()), Result::and)
+    }
+}
+
+impl Drop for LockAllGuard<'_> {
+    fn drop(&mut self) {
+        for file in self.files.iter().rev() {
+            let _ = FileExt::unlock(*file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use std::fs;
+
+    use super::*;
+    use crate::{lock_contended_error, FileExt};
+
+    /// Tests that a set of files is locked together, and that no file stays
+    /// locked when one of them cannot be locked.
+    #[test]
+    fn lock_all_rollback() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let open = |name: &str| {
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(tempdir.path().join(name))
+                .unwrap()
+        };
+        let (index, data, journal) = (open("index"), open("data"), open("journal"));
+        let (other_index, other_data, other_journal) =
+            (open("index"), open("data"), open("journal"));
+
+        let guard = lock_all(&[
+            (&index, LockMode::Shared),
+            (&data, LockMode::Exclusive),
+            (&journal, LockMode::Exclusive),
+        ])
+        .unwrap();
+        assert_eq!(guard.files().len(), 3);
+        FileExt::try_lock_shared(&other_index).unwrap();
+        FileExt::unlock(&other_index).unwrap();
+        assert_eq!(
+            FileExt::try_lock_shared(&other_data)
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        guard.unlock().unwrap();
+
+        // The journal is locked elsewhere, so neither of the others stays locked.
+        FileExt::try_lock_exclusive(&other_journal).unwrap();
+        let files = [
+            (&index, LockMode::Exclusive),
+            (&data, LockMode::Exclusive),
+            (&journal, LockMode::Shared),
+        ];
+        assert_eq!(
+            try_lock_all(&files).unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        FileExt::try_lock_exclusive(&other_index).unwrap();
+        FileExt::try_lock_exclusive(&other_data).unwrap();
+    }
+
+    /// Tests that a file given more than once is locked once, in the strongest
+    /// mode asked for.
+    #[test]
+    fn lock_all_duplicates() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::File::create(&path).unwrap();
+        let same_file = fs::File::open(&path).unwrap();
+
+        let guard =
+            lock_all(&[(&file, LockMode::Shared), (&same_file, LockMode::Exclusive)]).unwrap();
+        assert_eq!(guard.files().len(), 1);
+        let other = fs::File::open(&path).unwrap();
+        assert_eq!(
+            FileExt::try_lock_shared(&other).unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        drop(guard);
+        FileExt::try_lock_exclusive(&other).unwrap();
+    }
+}
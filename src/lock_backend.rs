@@ -0,0 +1,89 @@
+use std::io::{Error, Result};
+use std::sync::atomic::{AtomicU8, Ordering};
+
+/// The system call used to lock files on Unix.
+///
+/// Locks only interact with locks taken through the same primitive (except that
+/// POSIX and OFD locks conflict with each other), so processes sharing a lock
+/// file must agree on a backend. The whole-file lock methods of `FileExt` and
+/// the async traits use the crate-wide default (see
+/// [`set_default_lock_backend`]); [`FileExt::locks`](crate::FileExt::locks)
+/// and the `locks` methods of the async traits select a backend for a single
+/// file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockBackend {
+    /// [`flock(2)`](http://man7.org/linux/man-pages/man2/flock.2.html), as used
+    /// by `flock(1)`. Locks cover the whole file and belong to the open file
+    /// description. As `flock` has no byte ranges, range locks fall back to
+    /// POSIX record locks. This is the default.
+    Flock,
+    /// POSIX record locks ([`fcntl(2)`](http://man7.org/linux/man-pages/man2/fcntl.2.html)),
+    /// as used by SQLite and `lockf(3)`. Locks belong to the process: they never
+    /// conflict within it, and closing *any* descriptor of the file releases
+    /// all of them.
+    Posix,
+    /// Linux open file description locks. These have the byte-range semantics
+    /// of POSIX record locks, but belong to the open file description like
+    /// `flock` locks.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Ofd,
+}
+
+impl LockBackend {
+    fn from_bits(bits: u8) -> Self {
+        match bits & !FIXED {
+            x if x == LockBackend::Posix as u8 => LockBackend::Posix,
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            x if x == LockBackend::Ofd as u8 => LockBackend::Ofd,
+            _ => LockBackend::Flock,
+        }
+    }
+}
+
+/// Set in `DEFAULT_BACKEND` once the default has been set or used, after which
+/// it cannot change.
+const FIXED: u8 = 0x80;
+
+static DEFAULT_BACKEND: AtomicU8 = AtomicU8::new(LockBackend::Flock as u8);
+
+/// Sets the backend used by the whole-file and range lock methods of `FileExt`
+/// and the async traits, for every file in the process.
+///
+/// The default can only be chosen once, before any of those methods used it:
+/// a lock taken with one backend would not be released by an unlock with
+/// another. Fails if the default was already set to, or used as, a different
+/// backend. Note that `std::fs::File` has inherent lock methods of the same
+/// names as those of `FileExt`, which always use `flock`; call the trait
+/// methods explicitly, as in `FileExt::lock_exclusive(&file)`.
+pub fn set_default_lock_backend(backend: LockBackend) -> Result<()> {
+    let unset = LockBackend::Flock as u8;
+    match DEFAULT_BACKEND.compare_exchange(
+        unset,
+        backend as u8 | FIXED,
+        Ordering::AcqRel,
+        Ordering::Acquire,
+    ) {
+        Ok(_) => Ok(()),
+        Err(current) if LockBackend::from_bits(current) == backend => Ok(()),
+        Err(_) => Err(Error::other(
+            "the default lock backend was already set or used",
+        )),
+    }
+}
+
+/// Returns the crate-wide default lock backend (see [`set_default_lock_backend`]).
+pub fn default_lock_backend() -> LockBackend {
+    LockBackend::from_bits(DEFAULT_BACKEND.load(Ordering::Acquire))
+}
+
+/// Returns the default lock backend for a lock method to use, fixing it so
+/// that later locks and unlocks use the same backend.
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+pub(crate) fn use_default() -> LockBackend {
+    LockBackend::from_bits(DEFAULT_BACKEND.fetch_or(FIXED, Ordering::AcqRel))
+}
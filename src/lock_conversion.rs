@@ -0,0 +1,55 @@
+use std::error;
+use std::fmt;
+use std::io::Error;
+
+/// How `upgrade` or `downgrade` converted a lock between shared and exclusive
+/// mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockConversion {
+    /// The lock was converted in place, and was held throughout.
+    Atomic,
+    /// The lock was released and taken again in the new mode. Another process
+    /// may have locked, and modified, the file in between, so anything read
+    /// under the original lock should be read again.
+    Reacquired,
+}
+
+/// The inner error of a failed conversion after which the original lock could
+/// not be taken again either.
+#[derive(Debug)]
+struct LockLost {
+    source: Error,
+}
+
+impl fmt::Display for LockLost {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "file lock lost while converting it: {}", self.source)
+    }
+}
+
+impl error::Error for LockLost {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Returns `err`, the error a non-atomic conversion failed with, or a lock lost
+/// error if `restored`, the attempt to take the original lock again, failed.
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+pub(crate) fn restore(restored: std::io::Result<()>, err: Error) -> Error {
+    match restored {
+        Ok(()) => err,
+        Err(_) => Error::new(err.kind(), LockLost { source: err }),
+    }
+}
+
+/// Returns whether `err` was returned by a lock conversion which released the
+/// original lock and could not take it again, leaving the file unlocked.
+pub fn is_lock_lost(err: &Error) -> bool {
+    err.get_ref().is_some_and(|e| e.is::<LockLost>())
+}
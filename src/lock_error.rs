@@ -0,0 +1,266 @@
+use std::error;
+use std::fmt;
+use std::io::{Error, ErrorKind};
+
+use crate::sys;
+
+/// Why taking or releasing a file lock failed, as told by [`classify`].
+///
+/// Lock methods return `io::Error` like the rest of `std::fs`; this tells the
+/// errors callers usually handle apart without comparing `raw_os_error`
+/// against [`lock_contended_error`](crate::lock_contended_error). A
+/// `LockError` converts back into the `io::Error` it stands for.
+#[derive(Debug)]
+pub enum LockError {
+    /// A try lock method failed because another lock conflicts with the one
+    /// asked for.
+    Contended,
+    /// The file system, or the platform, does not support the lock.
+    Unsupported,
+    /// The operation was interrupted, as by a signal. The blocking lock
+    /// methods of this crate retry when a signal interrupts them, so this only
+    /// comes from errors of kind `Interrupted` raised elsewhere.
+    Interrupted,
+    /// A cancellable lock method gave up waiting because its
+    /// [`CancelToken`](crate::CancelToken) was cancelled.
+    Cancelled,
+    /// Waiting for the lock would have deadlocked, which only POSIX record
+    /// locks detect.
+    Deadlock,
+    /// The lock could not be taken before a deadline passed.
+    TimedOut,
+    /// Any other error.
+    Io(Error),
+}
+
+impl LockError {
+    /// Returns whether the lock was contended, the error to retry on.
+    pub fn is_contended(&self) -> bool {
+        matches!(self, LockError::Contended)
+    }
+}
+
+/// Classifies an error returned by a lock method.
+///
+/// Errors which were rebuilt around the one a lock method returned, with
+/// `Error::new(err.kind(), ...)`, are classified by that kind, or by the OS
+/// error code of an `io::Error` among their sources. `LockError::Io` holds a
+/// copy of `err`, which keeps its OS error code, or its kind and message for
+/// other errors.
+pub fn classify(err: &Error) -> LockError {
+    classify_known(err).unwrap_or_else(|| {
+        LockError::Io(match err.raw_os_error() {
+            Some(code) => Error::from_raw_os_error(code),
+            None => Error::new(err.kind(), err.to_string()),
+        })
+    })
+}
+
+impl From<Error> for LockError {
+    fn from(err: Error) -> Self {
+        classify_known(&err).unwrap_or(LockError::Io(err))
+    }
+}
+
+/// Returns the variant of `err` other than `LockError::Io`, if any.
+fn classify_known(err: &Error) -> Option<LockError> {
+    let code = os_error(err);
+    if code.is_some() && code == sys::lock_error().raw_os_error() {
+        return Some(LockError::Contended);
+    }
+    if err.get_ref().is_some_and(|inner| inner.is::<Cancelled>()) {
+        return Some(LockError::Cancelled);
+    }
+    if code.is_some() && code == sys::deadlock_error().raw_os_error() {
+        return Some(LockError::Deadlock);
+    }
+    // `ENOLCK` is what NFS mounts without a lock manager fail with.
+    #[cfg(unix)]
+    if code == Some(rustix::io::Errno::NOLCK.raw_os_error()) {
+        return Some(LockError::Unsupported);
+    }
+    match err.kind() {
+        // The kind of `lock_contended_error` on Unix, kept by errors rebuilt
+        // with a message.
+        ErrorKind::WouldBlock => Some(LockError::Contended),
+        ErrorKind::Unsupported => Some(LockError::Unsupported),
+        ErrorKind::Interrupted => Some(LockError::Interrupted),
+        ErrorKind::TimedOut => Some(LockError::TimedOut),
+        _ => None,
+    }
+}
+
+/// Returns the OS error code of `err`, or else of the first `io::Error` with
+/// one among its sources.
+fn os_error(err: &Error) -> Option<i32> {
+    if let Some(code) = err.raw_os_error() {
+        return Some(code);
+    }
+    let mut source = err
+        .get_ref()
+        .map(|inner| inner as &(dyn error::Error + 'static));
+    while let Some(inner) = source {
+        if let Some(code) = inner.downcast_ref::<Error>().and_then(Error::raw_os_error) {
+            return Some(code);
+        }
+        source = inner.source();
+    }
+    None
+}
+
+impl From<LockError> for Error {
+    fn from(err: LockError) -> Self {
+        match err {
+            LockError::Contended => sys::lock_error(),
+            LockError::Unsupported => {
+                Error::new(ErrorKind::Unsupported, "file locking is not supported")
+            }
+            LockError::Interrupted => ErrorKind::Interrupted.into(),
+            LockError::Cancelled => cancelled_error(),
+            LockError::Deadlock => sys::deadlock_error(),
+            LockError::TimedOut => crate::retry::timed_out_error(),
+            LockError::Io(err) => err,
+        }
+    }
+}
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LockError::Contended => f.write_str("the file is locked"),
+            LockError::Unsupported => f.write_str("file locking is not supported"),
+            LockError::Interrupted => f.write_str("interrupted while waiting for the file lock"),
+            LockError::Cancelled => Cancelled.fmt(f),
+            LockError::Deadlock => f.write_str("waiting for the file lock would deadlock"),
+            LockError::TimedOut => f.write_str("timed out waiting for the file lock"),
+            LockError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl error::Error for LockError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            LockError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// The inner error of a cancelled wait for a lock.
+#[derive(Debug)]
+struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("waiting for the file lock was cancelled")
+    }
+}
+
+impl error::Error for Cancelled {}
+
+/// Returns the error a cancellable lock method fails with once its token is
+/// cancelled. Its kind is `Interrupted`, and it is classified as
+/// `LockError::Cancelled`.
+pub(crate) fn cancelled_error() -> Error {
+    Error::new(ErrorKind::Interrupted, Cancelled)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lock_contended_error;
+
+    #[derive(Debug)]
+    struct Wrapped(Error);
+
+    impl fmt::Display for Wrapped {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "wrapped: {}", self.0)
+        }
+    }
+
+    impl error::Error for Wrapped {
+        fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    /// Tests that errors are classified, and convert back to what they were.
+    #[test]
+    fn classify_errors() {
+        let contended = classify(&lock_contended_error());
+        assert!(contended.is_contended());
+        assert_eq!(
+            Error::from(contended).raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        let deadlock = classify(&sys::deadlock_error());
+        assert!(matches!(deadlock, LockError::Deadlock));
+        assert_eq!(
+            Error::from(deadlock).raw_os_error(),
+            sys::deadlock_error().raw_os_error()
+        );
+
+        assert!(matches!(
+            classify(&ErrorKind::Interrupted.into()),
+            LockError::Interrupted
+        ));
+        assert!(matches!(
+            classify(&ErrorKind::Unsupported.into()),
+            LockError::Unsupported
+        ));
+        assert!(matches!(
+            classify(&crate::retry::timed_out_error()),
+            LockError::TimedOut
+        ));
+        assert_eq!(Error::from(LockError::TimedOut).kind(), ErrorKind::TimedOut);
+        let cancelled = classify(&cancelled_error());
+        assert!(matches!(cancelled, LockError::Cancelled));
+        assert_eq!(Error::from(cancelled).kind(), ErrorKind::Interrupted);
+
+        // Errors rebuilt with a message keep their classification.
+        let rebuilt = Error::new(ErrorKind::WouldBlock, "the file is busy");
+        assert!(classify(&rebuilt).is_contended());
+        let wrapped = Error::other(Wrapped(sys::deadlock_error()));
+        assert!(matches!(classify(&wrapped), LockError::Deadlock));
+
+        let other = Error::new(ErrorKind::PermissionDenied, "denied");
+        match classify(&other) {
+            LockError::Io(err) => {
+                assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+                assert_eq!(err.to_string(), "denied");
+            }
+            err => panic!("unexpected {:?}", err),
+        }
+    }
+
+    /// Tests that a contended try lock is classified as such.
+    #[cfg(feature = "sync")]
+    #[test]
+    fn classify_contended_lock() {
+        extern crate tempdir;
+        use crate::FileExt;
+        use std::fs;
+
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        FileExt::lock_exclusive(&file1).unwrap();
+        let err = FileExt::try_lock_shared(&file2).unwrap_err();
+        assert!(classify(&err).is_contended());
+    }
+}
@@ -0,0 +1,57 @@
+use std::fmt;
+
+use crate::LockMode;
+
+/// A lock which conflicts with a lock that was asked for, as reported by
+/// `lock_holder`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LockHolder {
+    pub(crate) mode: LockMode,
+    pub(crate) offset: u64,
+    pub(crate) len: u64,
+    pub(crate) pid: Option<u32>,
+}
+
+impl LockHolder {
+    /// Returns the mode of the conflicting lock.
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Returns the offset of the first byte covered by the conflicting lock.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Returns the number of bytes covered by the conflicting lock, or zero if
+    /// it extends to the end of the file.
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Returns the ID of the process holding the conflicting lock. This is
+    /// not known for OFD locks, which are not owned by a process. For `flock`
+    /// locks it is the process which took the lock, which may since have
+    /// exited, leaving the lock to another process it shared the file with.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mode = match self.mode {
+            LockMode::Shared => "shared",
+            LockMode::Exclusive => "exclusive",
+        };
+        write!(f, "{} lock on bytes {}..", mode, self.offset)?;
+        if self.len != 0 {
+            write!(f, "{}", self.offset.saturating_add(self.len))?;
+        }
+        match self.pid {
+            Some(pid) => write!(f, " held by pid {}", pid),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,8 @@
+/// The kind of lock to take on a file, or on a range of bytes in it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockMode {
+    /// A shared (read) lock. Any number of shared locks may be held at once.
+    Shared,
+    /// An exclusive (read-write) lock, which excludes all other locks.
+    Exclusive,
+}
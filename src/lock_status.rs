@@ -0,0 +1,32 @@
+/// Whether a file is locked, as reported by `lock_status`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockStatus {
+    /// No lock is held on the file.
+    Unlocked,
+    /// One or more shared locks are held on the file.
+    SharedHeld {
+        /// Whether one of the shared locks is held by the calling process.
+        held_by_self: bool,
+    },
+    /// An exclusive lock is held on the file.
+    ExclusiveHeld {
+        /// Whether the exclusive lock is held by the calling process.
+        held_by_self: bool,
+    },
+}
+
+impl LockStatus {
+    /// Returns whether any lock is held on the file.
+    pub fn is_locked(&self) -> bool {
+        *self != LockStatus::Unlocked
+    }
+
+    /// Returns whether the calling process holds a lock on the file.
+    pub fn is_held_by_self(&self) -> bool {
+        match *self {
+            LockStatus::Unlocked => false,
+            LockStatus::SharedHeld { held_by_self }
+            | LockStatus::ExclusiveHeld { held_by_self } => held_by_self,
+        }
+    }
+}
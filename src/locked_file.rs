@@ -0,0 +1,121 @@
+use std::error;
+use std::fmt;
+use std::io::Error;
+
+use crate::LockMode;
+
+mod private {
+    pub trait Sealed {}
+}
+
+/// The lock mode a `LockedFile` holds its file in: [`Shared`] or [`Exclusive`].
+pub trait LockKind: private::Sealed {
+    /// The mode of the lock.
+    const MODE: LockMode;
+}
+
+/// Marks a `LockedFile` which holds a shared lock, and may only be read.
+#[derive(Debug)]
+pub enum Shared {}
+
+/// Marks a `LockedFile` which holds an exclusive lock, and may be read and
+/// written.
+#[derive(Debug)]
+pub enum Exclusive {}
+
+impl private::Sealed for Shared {}
+impl private::Sealed for Exclusive {}
+
+impl LockKind for Shared {
+    const MODE: LockMode = LockMode::Shared;
+}
+
+impl LockKind for Exclusive {
+    const MODE: LockMode = LockMode::Exclusive;
+}
+
+/// The error returned when a `LockedFile` could not be converted to another
+/// mode, which carries back the `LockedFile` `F` that was to be converted, or
+/// the unlocked file `U` if the lock was lost.
+///
+/// The file keeps its original lock, unless the conversion released it and
+/// could not take it again, in which case [`is_lock_lost`](crate::is_lock_lost)
+/// returns `true` for the error, and only the file itself, no longer locked, is
+/// returned.
+#[derive(Debug)]
+pub struct ConversionError<F, U> {
+    file: Box<std::result::Result<F, U>>,
+    error: Error,
+}
+
+impl<F, U> ConversionError<F, U> {
+    #[cfg(any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ))]
+    pub(crate) fn new(file: F, error: Error) -> Self {
+        Self {
+            file: Box::new(Ok(file)),
+            error,
+        }
+    }
+
+    #[cfg(any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ))]
+    pub(crate) fn lock_lost(file: U, error: Error) -> Self {
+        Self {
+            file: Box::new(Err(file)),
+            error,
+        }
+    }
+
+    /// Returns the error the conversion failed with.
+    pub fn error(&self) -> &Error {
+        &self.error
+    }
+
+    /// Returns the `LockedFile` that was to be converted, still holding its
+    /// original lock, or the unlocked file in `Err` if the lock was lost,
+    /// dropping the error.
+    pub fn into_file(self) -> std::result::Result<F, U> {
+        *self.file
+    }
+
+    /// Returns the file as [`into_file`](Self::into_file) does, and the error.
+    pub fn into_parts(self) -> (std::result::Result<F, U>, Error) {
+        (*self.file, self.error)
+    }
+}
+
+impl<F, U> fmt::Display for ConversionError<F, U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to convert a locked file: {}", self.error)
+    }
+}
+
+impl<F: fmt::Debug, U: fmt::Debug> error::Error for ConversionError<F, U> {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
+
+impl<F, U> From<ConversionError<F, U>> for Error {
+    fn from(err: ConversionError<F, U>) -> Self {
+        err.error
+    }
+}
+
+cfg_sync!(
+    mod sync_impl;
+    pub use sync_impl::LockedFile;
+);
+
+cfg_async!(
+    pub(crate) mod async_impl;
+);
@@ -0,0 +1,178 @@
+macro_rules! async_locked_file {
+    ($file: ty, $file_name: literal) => {
+        use std::io::Result;
+        use std::marker::PhantomData;
+        use std::pin::Pin;
+        use std::task::{Context, Poll};
+
+        use crate::locked_file::{ConversionError, Exclusive, LockKind, Shared};
+        use crate::{is_lock_lost, LockMode};
+
+        #[doc = concat!("A `", $file_name, "` which is locked for as long as it is owned, in the mode `M`")]
+        /// ([`Shared`](crate::Shared) or [`Exclusive`](crate::Exclusive)).
+        ///
+        /// `LockedFile` implements the runtime's read and seek traits, and its write
+        /// trait only while it holds an exclusive lock, so functions which write to a
+        /// locked file can require a `&mut LockedFile<Exclusive>`. The lock is released
+        /// when the file is closed, so dropping a `LockedFile` unlocks it.
+        #[derive(Debug)]
+        pub struct LockedFile<M: LockKind> {
+            file: $file,
+            mode: PhantomData<fn() -> M>,
+        }
+
+        impl<M: LockKind> LockedFile<M> {
+            /// Locks `file` in mode `M`, waiting while it is locked incompatibly.
+            pub async fn lock(file: $file) -> Result<Self> {
+                match M::MODE {
+                    LockMode::Shared => AsyncFileExt::lock_shared(&file).await?,
+                    LockMode::Exclusive => AsyncFileExt::lock_exclusive(&file).await?,
+                }
+                Ok(Self::new(file))
+            }
+
+            /// Locks `file` in mode `M`, or returns an error if it is locked
+            /// incompatibly (see `lock_contended_error`).
+            pub fn try_lock(file: $file) -> Result<Self> {
+                match M::MODE {
+                    LockMode::Shared => AsyncFileExt::try_lock_shared(&file)?,
+                    LockMode::Exclusive => AsyncFileExt::try_lock_exclusive(&file)?,
+                }
+                Ok(Self::new(file))
+            }
+
+            fn new(file: $file) -> Self {
+                Self {
+                    file,
+                    mode: PhantomData,
+                }
+            }
+
+            /// Returns the locked file.
+            ///
+            #[doc = concat!("`&", $file_name, "` implements the runtime's write trait, so the file can be")]
+            /// written through the returned reference even while only a shared lock is
+            /// held; the mode only restricts writes through the `LockedFile` itself.
+            pub fn get_ref(&self) -> &$file {
+                &self.file
+            }
+
+            /// Flushes the file, then unlocks it and returns it.
+            pub async fn unlock(mut self) -> Result<$file> {
+                self.flush_file().await?;
+                AsyncFileExt::unlock(&self.file)?;
+                Ok(self.file)
+            }
+
+            fn file(self: Pin<&mut Self>) -> Pin<&mut $file> {
+                Pin::new(&mut self.get_mut().file)
+            }
+
+            /// Flushes writes the runtime still buffers, or has in flight, to the
+            /// file, so that they land before its lock is released or downgraded.
+            async fn flush_file(&mut self) -> Result<()> {
+                std::future::poll_fn(|cx| Pin::new(&mut self.file).poll_flush(cx)).await
+            }
+        }
+
+        impl LockedFile<Shared> {
+            /// Converts the shared lock to an exclusive lock with `upgrade`, waiting
+            /// while other shared locks are held.
+            ///
+            /// The conversion is subject to the semantics of the lock backend (see
+            /// [`AsyncFileExt::upgrade`]); if it fails, the `LockedFile` is returned in
+            /// the error, or only the file if the lock was lost.
+            pub async fn into_exclusive(self) -> std::result::Result<LockedFile<Exclusive>, ConversionError<Self, $file>> {
+                match AsyncFileExt::upgrade(&self.file).await {
+                    Ok(_) => Ok(LockedFile::new(self.file)),
+                    Err(e) if is_lock_lost(&e) => Err(ConversionError::lock_lost(self.file, e)),
+                    Err(e) => Err(ConversionError::new(self, e)),
+                }
+            }
+
+            /// Converts the shared lock to an exclusive lock with `try_upgrade`, or
+            /// returns the `LockedFile` in an error if other shared locks are held
+            /// (see `lock_contended_error`).
+            pub fn try_into_exclusive(self) -> std::result::Result<LockedFile<Exclusive>, ConversionError<Self, $file>> {
+                match AsyncFileExt::try_upgrade(&self.file) {
+                    Ok(_) => Ok(LockedFile::new(self.file)),
+                    Err(e) if is_lock_lost(&e) => Err(ConversionError::lock_lost(self.file, e)),
+                    Err(e) => Err(ConversionError::new(self, e)),
+                }
+            }
+        }
+
+        impl LockedFile<Exclusive> {
+            /// Flushes the file, then converts the exclusive lock to a shared lock with
+            /// `downgrade`. If the flush or the conversion fails, the `LockedFile` is
+            /// returned in the error, or only the file if the lock was lost.
+            pub async fn into_shared(mut self) -> std::result::Result<LockedFile<Shared>, ConversionError<Self, $file>> {
+                if let Err(e) = self.flush_file().await {
+                    return Err(ConversionError::new(self, e));
+                }
+                match AsyncFileExt::downgrade(&self.file) {
+                    Ok(_) => Ok(LockedFile::new(self.file)),
+                    Err(e) if is_lock_lost(&e) => Err(ConversionError::lock_lost(self.file, e)),
+                    Err(e) => Err(ConversionError::new(self, e)),
+                }
+            }
+        }
+    };
+}
+
+/// Implements the `futures-io` traits, as re-exported by smol and async-std,
+/// for `LockedFile`.
+#[cfg(any(feature = "smol", feature = "async-std"))]
+macro_rules! futures_io_impl {
+    ($read: path, $write: path, $seek: path) => {
+        impl<M: LockKind> $read for LockedFile<M> {
+            fn poll_read(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &mut [u8],
+            ) -> Poll<Result<usize>> {
+                self.file().poll_read(cx, buf)
+            }
+        }
+
+        impl<M: LockKind> $seek for LockedFile<M> {
+            fn poll_seek(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                pos: std::io::SeekFrom,
+            ) -> Poll<Result<u64>> {
+                self.file().poll_seek(cx, pos)
+            }
+        }
+
+        impl $write for LockedFile<Exclusive> {
+            fn poll_write(
+                self: Pin<&mut Self>,
+                cx: &mut Context<'_>,
+                buf: &[u8],
+            ) -> Poll<Result<usize>> {
+                self.file().poll_write(cx, buf)
+            }
+
+            fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+                self.file().poll_flush(cx)
+            }
+
+            fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+                self.file().poll_close(cx)
+            }
+        }
+    };
+}
+
+cfg_async_std! {
+    pub(crate) mod async_std_impl;
+}
+
+cfg_smol! {
+    pub(crate) mod smol_impl;
+}
+
+cfg_tokio! {
+    pub(crate) mod tokio_impl;
+}
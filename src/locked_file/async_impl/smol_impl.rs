@@ -0,0 +1,75 @@
+use smol::fs::File;
+use smol::io::AsyncWrite;
+
+use crate::file_ext::async_impl::smol_impl::AsyncFileExt;
+
+async_locked_file!(File, "smol::fs::File");
+futures_io_impl!(
+    smol::io::AsyncRead,
+    smol::io::AsyncWrite,
+    smol::io::AsyncSeek
+);
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use smol::fs;
+    use smol::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+    use std::io::SeekFrom;
+
+    use crate::{lock_contended_error, smol::LockedFile, Exclusive, Shared};
+
+    async fn write_header(file: &mut LockedFile<Exclusive>, header: &[u8]) {
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        file.write_all(header).await.unwrap();
+        file.flush().await.unwrap();
+    }
+
+    /// Tests that a locked file holds its lock, and converts it between modes.
+    #[smol_potat::test]
+    async fn locked_file() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let mut options = fs::OpenOptions::new();
+        options.read(true).write(true).create(true);
+
+        let mut file = LockedFile::<Exclusive>::lock(options.open(&path).await.unwrap())
+            .await
+            .unwrap();
+        write_header(&mut file, b"fs4").await;
+        assert_eq!(
+            LockedFile::<Shared>::try_lock(options.open(&path).await.unwrap())
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        let mut file = file.into_shared().await.unwrap();
+        let reader = LockedFile::<Shared>::try_lock(options.open(&path).await.unwrap()).unwrap();
+        let mut contents = String::new();
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        file.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "fs4");
+
+        // The upgrade fails while another reader holds the file, and hands
+        // back the file, still locked shared.
+        let err = file.try_into_exclusive().unwrap_err();
+        assert_eq!(
+            err.error().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        let file = err.into_file().unwrap();
+        assert_eq!(
+            LockedFile::<Exclusive>::try_lock(options.open(&path).await.unwrap())
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        drop(reader);
+        let mut file = file.into_exclusive().await.unwrap();
+        write_header(&mut file, b"FS4").await;
+        file.unlock().await.unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+use tokio::fs::File;
+use tokio::io::{AsyncRead, AsyncSeek, AsyncWrite, ReadBuf};
+
+use crate::file_ext::async_impl::tokio_impl::AsyncFileExt;
+
+async_locked_file!(File, "tokio::fs::File");
+
+impl<M: LockKind> AsyncRead for LockedFile<M> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<()>> {
+        self.file().poll_read(cx, buf)
+    }
+}
+
+impl<M: LockKind> AsyncSeek for LockedFile<M> {
+    fn start_seek(self: Pin<&mut Self>, position: std::io::SeekFrom) -> Result<()> {
+        self.file().start_seek(position)
+    }
+
+    fn poll_complete(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<u64>> {
+        self.file().poll_complete(cx)
+    }
+}
+
+impl AsyncWrite for LockedFile<Exclusive> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize>> {
+        self.file().poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.file().poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.file().poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use std::io::SeekFrom;
+    use tokio::fs;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+    use crate::{lock_contended_error, tokio::LockedFile, Exclusive, Shared};
+
+    async fn write_header(file: &mut LockedFile<Exclusive>, header: &[u8]) {
+        file.seek(SeekFrom::Start(0)).await.unwrap();
+        file.write_all(header).await.unwrap();
+    }
+
+    /// Tests that a locked file holds its lock, and converts it between modes.
+    #[tokio::test]
+    async fn locked_file() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let options = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .clone();
+
+        let mut file = LockedFile::<Exclusive>::lock(options.open(&path).await.unwrap())
+            .await
+            .unwrap();
+        write_header(&mut file, b"fs4").await;
+        assert_eq!(
+            LockedFile::<Shared>::try_lock(options.open(&path).await.unwrap())
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        // The header is flushed before the lock is downgraded, so another
+        // reader sees it.
+        let file = file.into_shared().await.unwrap();
+        let mut reader =
+            LockedFile::<Shared>::try_lock(options.open(&path).await.unwrap()).unwrap();
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents).await.unwrap();
+        assert_eq!(contents, "fs4");
+
+        // The upgrade fails while another reader holds the file, and hands
+        // back the file, still locked shared.
+        let err = file.try_into_exclusive().unwrap_err();
+        assert_eq!(
+            err.error().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        let file = err.into_file().unwrap();
+        assert_eq!(
+            LockedFile::<Exclusive>::try_lock(options.open(&path).await.unwrap())
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        drop(reader);
+        let mut file = file.into_exclusive().await.unwrap();
+        write_header(&mut file, b"FS4").await;
+        file.unlock().await.unwrap();
+    }
+}
@@ -0,0 +1,222 @@
+use std::fs::File;
+use std::io::{IoSlice, IoSliceMut, Read, Result, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+
+use super::{ConversionError, Exclusive, LockKind, Shared};
+use crate::{is_lock_lost, FileExt, LockMode};
+
+/// A `File` which is locked for as long as it is owned, in the mode `M`
+/// ([`Shared`] or [`Exclusive`]).
+///
+/// `LockedFile` implements `Read` and `Seek`, and `Write` only while it holds
+/// an exclusive lock, so functions which write to a locked file can require a
+/// `&mut LockedFile<Exclusive>`. The lock is released when the file is closed,
+/// so dropping a `LockedFile` unlocks it.
+#[derive(Debug)]
+pub struct LockedFile<M: LockKind> {
+    file: File,
+    mode: PhantomData<M>,
+}
+
+impl<M: LockKind> LockedFile<M> {
+    /// Locks `file` in mode `M`, blocking while it is locked incompatibly.
+    pub fn lock(file: File) -> Result<Self> {
+        match M::MODE {
+            LockMode::Shared => FileExt::lock_shared(&file)?,
+            LockMode::Exclusive => FileExt::lock_exclusive(&file)?,
+        }
+        Ok(Self::new(file))
+    }
+
+    /// Locks `file` in mode `M`, or returns an error if it is locked
+    /// incompatibly (see `lock_contended_error`).
+    pub fn try_lock(file: File) -> Result<Self> {
+        match M::MODE {
+            LockMode::Shared => FileExt::try_lock_shared(&file)?,
+            LockMode::Exclusive => FileExt::try_lock_exclusive(&file)?,
+        }
+        Ok(Self::new(file))
+    }
+
+    fn new(file: File) -> Self {
+        Self {
+            file,
+            mode: PhantomData,
+        }
+    }
+
+    /// Returns the locked file.
+    ///
+    /// `&File` implements `Write`, so the file can be written through the
+    /// returned reference even while only a shared lock is held; the mode only
+    /// restricts writes through the `LockedFile` itself.
+    pub fn get_ref(&self) -> &File {
+        &self.file
+    }
+
+    /// Unlocks the file and returns it.
+    pub fn unlock(self) -> Result<File> {
+        FileExt::unlock(&self.file)?;
+        Ok(self.file)
+    }
+}
+
+impl LockedFile<Shared> {
+    /// Converts the shared lock to an exclusive lock with `upgrade`, blocking
+    /// while other shared locks are held.
+    ///
+    /// The conversion is subject to the semantics of the lock backend (see
+    /// [`FileExt::upgrade`]); if it fails, the `LockedFile` is returned in the
+    /// error, or only the `File` if the lock was lost.
+    pub fn into_exclusive(
+        self,
+    ) -> std::result::Result<LockedFile<Exclusive>, ConversionError<Self, File>> {
+        match FileExt::upgrade(&self.file) {
+            Ok(_) => Ok(LockedFile::new(self.file)),
+            Err(e) if is_lock_lost(&e) => Err(ConversionError::lock_lost(self.file, e)),
+            Err(e) => Err(ConversionError::new(self, e)),
+        }
+    }
+
+    /// Converts the shared lock to an exclusive lock with `try_upgrade`, or
+    /// returns the `LockedFile` in an error if other shared locks are held
+    /// (see `lock_contended_error`).
+    pub fn try_into_exclusive(
+        self,
+    ) -> std::result::Result<LockedFile<Exclusive>, ConversionError<Self, File>> {
+        match FileExt::try_upgrade(&self.file) {
+            Ok(_) => Ok(LockedFile::new(self.file)),
+            Err(e) if is_lock_lost(&e) => Err(ConversionError::lock_lost(self.file, e)),
+            Err(e) => Err(ConversionError::new(self, e)),
+        }
+    }
+}
+
+impl LockedFile<Exclusive> {
+    /// Converts the exclusive lock to a shared lock with `downgrade`. If the
+    /// conversion fails, the `LockedFile` is returned in the error, or only the
+    /// `File` if the lock was lost.
+    pub fn into_shared(
+        self,
+    ) -> std::result::Result<LockedFile<Shared>, ConversionError<Self, File>> {
+        match FileExt::downgrade(&self.file) {
+            Ok(_) => Ok(LockedFile::new(self.file)),
+            Err(e) if is_lock_lost(&e) => Err(ConversionError::lock_lost(self.file, e)),
+            Err(e) => Err(ConversionError::new(self, e)),
+        }
+    }
+}
+
+impl<M: LockKind> Read for LockedFile<M> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.file.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        self.file.read_vectored(bufs)
+    }
+
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        self.file.read_to_end(buf)
+    }
+
+    fn read_to_string(&mut self, buf: &mut String) -> Result<usize> {
+        self.file.read_to_string(buf)
+    }
+}
+
+impl<M: LockKind> Seek for LockedFile<M> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+impl Write for LockedFile<Exclusive> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        self.file.write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::io::{Read, Seek, SeekFrom, Write};
+
+    use super::*;
+    use crate::lock_contended_error;
+
+    fn write_header(file: &mut LockedFile<Exclusive>, header: &[u8]) {
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.write_all(header).unwrap();
+    }
+
+    /// Tests that a locked file holds its lock, and converts it between modes.
+    #[test]
+    fn locked_file() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let open = || {
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .unwrap()
+        };
+
+        let mut file = LockedFile::<Exclusive>::lock(open()).unwrap();
+        write_header(&mut file, b"fs4");
+        assert_eq!(
+            LockedFile::<Shared>::try_lock(open())
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        let mut file = file.into_shared().unwrap();
+        let reader = LockedFile::<Shared>::try_lock(open()).unwrap();
+        let mut contents = String::new();
+        file.seek(SeekFrom::Start(0)).unwrap();
+        file.read_to_string(&mut contents).unwrap();
+        assert_eq!(contents, "fs4");
+        assert_eq!(
+            LockedFile::<Exclusive>::try_lock(open())
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        // The upgrade fails while another reader holds the file, and hands
+        // back the file, still locked shared.
+        let err = file.try_into_exclusive().unwrap_err();
+        assert_eq!(
+            err.error().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        let file = err.into_file().unwrap();
+        assert_eq!(
+            LockedFile::<Exclusive>::try_lock(open())
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        // Dropping the other reader allows the upgrade.
+        drop(reader);
+        let mut file = file.into_exclusive().unwrap();
+        write_header(&mut file, b"FS4");
+        let file = file.unlock().unwrap();
+        FileExt::try_lock_exclusive(&file).unwrap();
+    }
+}
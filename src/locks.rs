@@ -0,0 +1,308 @@
+//! Inspection of every file lock held on the system, as listed in `/proc/locks`.
+//!
+//! This is meant for debugging: when a try lock method fails with
+//! `lock_contended_error`, [`locks_on`] tells which processes hold locks on the
+//! file, and which are waiting for one.
+
+use std::fmt;
+use std::fs;
+use std::io::{ErrorKind, Result};
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use crate::LockMode;
+
+/// The kind of a lock listed in `/proc/locks`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum LockType {
+    /// A whole-file lock taken with `flock`.
+    Flock,
+    /// A POSIX record lock, owned by a process.
+    Posix,
+    /// An open file description lock.
+    Ofd,
+    /// A file lease.
+    Lease,
+    /// An NFS delegation.
+    Delegation,
+}
+
+/// A lock held, or waited for, on some file on the system.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SystemLock {
+    id: u64,
+    blocked: bool,
+    kind: LockType,
+    mode: LockMode,
+    pid: Option<u32>,
+    device: u64,
+    inode: u64,
+    start: u64,
+    end: Option<u64>,
+}
+
+impl SystemLock {
+    /// Returns the ID of the lock in `/proc/locks`. A blocked request shares
+    /// the ID of the lock it waits for.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns whether this is a request waiting for a conflicting lock rather
+    /// than a lock which is held.
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
+
+    /// Returns the kind of the lock.
+    pub fn kind(&self) -> LockType {
+        self.kind
+    }
+
+    /// Returns the mode of the lock.
+    pub fn mode(&self) -> LockMode {
+        self.mode
+    }
+
+    /// Returns the ID of the process which took the lock. This is not known
+    /// for OFD locks, and for `flock` locks the process may since have exited,
+    /// leaving the lock to another process it shared the file with.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+
+    /// Returns the ID of the device holding the locked file, as in
+    /// `MetadataExt::dev`.
+    pub fn device(&self) -> u64 {
+        self.device
+    }
+
+    /// Returns the inode number of the locked file.
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
+
+    /// Returns the offset of the first byte covered by the lock.
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the offset of the last byte covered by the lock, or `None` if
+    /// it extends to the end of the file.
+    pub fn end(&self) -> Option<u64> {
+        self.end
+    }
+}
+
+impl fmt::Display for SystemLock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            LockType::Flock => "flock",
+            LockType::Posix => "POSIX",
+            LockType::Ofd => "OFD",
+            LockType::Lease => "lease",
+            LockType::Delegation => "delegation",
+        };
+        let mode = match self.mode {
+            LockMode::Shared => "shared",
+            LockMode::Exclusive => "exclusive",
+        };
+        if self.blocked {
+            write!(f, "waiting for ")?;
+        }
+        write!(f, "{} {} lock on bytes {}..", mode, kind, self.start)?;
+        if let Some(end) = self.end {
+            write!(f, "={}", end)?;
+        }
+        match self.pid {
+            Some(pid) => write!(f, " by pid {}", pid),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Returns every lock held, or waited for, on the system.
+///
+/// Lines of `/proc/locks` which are not understood, such as leases being
+/// broken, are skipped.
+pub fn system_locks() -> Result<Vec<SystemLock>> {
+    fs::read_to_string("/proc/locks").map(|locks| parse(&locks))
+}
+
+/// Returns every lock held, or waited for, on the file at `path`.
+///
+/// Locks are matched by the device and inode of the file. `/proc/locks` lists
+/// the device of the file system, which differs from the device reported for
+/// files on btrfs subvolumes and on other file systems which report an
+/// anonymous device, so no locks are found on such files.
+pub fn locks_on<P>(path: P) -> Result<Vec<SystemLock>>
+where
+    P: AsRef<Path>,
+{
+    let metadata = fs::metadata(path)?;
+    let mut locks = system_locks()?;
+    locks.retain(|lock| lock.device == metadata.dev() && lock.inode == metadata.ino());
+    Ok(locks)
+}
+
+/// Returns the locks held, or waited for, through the open file description of
+/// `fd`, as listed in the `lock:` lines of `/proc/self/fdinfo/<fd>`.
+///
+/// Their IDs are numbered per descriptor, and do not match `/proc/locks`.
+pub(crate) fn fd_locks(fd: std::os::unix::io::RawFd) -> Result<Vec<SystemLock>> {
+    let info = fs::read_to_string(format!("/proc/self/fdinfo/{}", fd))?;
+    Ok(info
+        .lines()
+        .filter_map(|line| parse_line(line.strip_prefix("lock:")?))
+        .collect())
+}
+
+/// Returns the locks held, or waited for, through every descriptor open in the
+/// calling process (see [`fd_locks`]).
+pub(crate) fn process_locks() -> Result<Vec<SystemLock>> {
+    let mut locks = Vec::new();
+    for entry in fs::read_dir("/proc/self/fdinfo")? {
+        let Some(fd) = entry?.file_name().to_str().and_then(|fd| fd.parse().ok()) else {
+            continue;
+        };
+        match fd_locks(fd) {
+            Ok(fd_locks) => locks.extend(fd_locks),
+            // The descriptor was closed since the directory was read.
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(locks)
+}
+
+fn parse(locks: &str) -> Vec<SystemLock> {
+    locks.lines().filter_map(parse_line).collect()
+}
+
+/// Parses a line such as `1: POSIX  ADVISORY  WRITE 1234 08:01:5678 0 EOF`,
+/// or `1: -> FLOCK  ADVISORY  READ 1235 08:01:5678 0 EOF` for a blocked request.
+fn parse_line(line: &str) -> Option<SystemLock> {
+    let mut fields = line.split_whitespace().peekable();
+    let id = fields.next()?.strip_suffix(':')?.parse().ok()?;
+    let blocked = fields.next_if_eq(&"->").is_some();
+    let kind = match fields.next()? {
+        "FLOCK" => LockType::Flock,
+        "POSIX" => LockType::Posix,
+        "OFDLCK" => LockType::Ofd,
+        "LEASE" => LockType::Lease,
+        "DELEG" => LockType::Delegation,
+        _ => return None,
+    };
+    // ADVISORY or MANDATORY for locks, the lease state for leases.
+    fields.next()?;
+    let mode = match fields.next()? {
+        "READ" => LockMode::Shared,
+        "WRITE" => LockMode::Exclusive,
+        _ => return None,
+    };
+    let pid = fields.next()?.parse().ok();
+
+    let mut file = fields.next()?.split(':');
+    let major = u32::from_str_radix(file.next()?, 16).ok()?;
+    let minor = u32::from_str_radix(file.next()?, 16).ok()?;
+    let inode = file.next()?.parse().ok()?;
+
+    let start = fields.next()?.parse().ok()?;
+    let end = match fields.next()? {
+        "EOF" => None,
+        end => Some(end.parse().ok()?),
+    };
+    Some(SystemLock {
+        id,
+        blocked,
+        kind,
+        mode,
+        pid,
+        device: rustix::fs::makedev(major, minor),
+        inode,
+        start,
+        end,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use super::*;
+    #[cfg(feature = "sync")]
+    use crate::{FileExt, LockBackend};
+
+    /// Tests parsing of every kind of line in `/proc/locks`.
+    #[test]
+    fn parse_proc_locks() {
+        let locks = parse(
+            "1: POSIX  ADVISORY  WRITE 1234 08:01:5678 0 EOF\n\
+             1: -> POSIX  ADVISORY  READ 1235 08:01:5678 10 19\n\
+             2: OFDLCK ADVISORY  READ  -1 fd:00:42 0 0\n\
+             3: FLOCK  ADVISORY  WRITE 99 00:2f:7 0 EOF\n\
+             4: LEASE  BREAKING  UNLCK 100 00:2f:8 0 EOF\n\
+             5: DELEG  ACTIVE    READ 101 00:2f:9 0 EOF\n",
+        );
+        assert_eq!(locks.len(), 5);
+        assert_eq!(
+            locks[1],
+            SystemLock {
+                id: 1,
+                blocked: true,
+                kind: LockType::Posix,
+                mode: LockMode::Shared,
+                pid: Some(1235),
+                device: rustix::fs::makedev(8, 1),
+                inode: 5678,
+                start: 10,
+                end: Some(19),
+            }
+        );
+        assert_eq!(
+            locks[1].to_string(),
+            "waiting for shared POSIX lock on bytes 10..=19 by pid 1235"
+        );
+        assert_eq!(locks[2].kind(), LockType::Ofd);
+        assert_eq!(locks[2].pid(), None);
+        assert_eq!(locks[2].device(), rustix::fs::makedev(0xfd, 0));
+        assert_eq!(
+            locks[3].to_string(),
+            "exclusive flock lock on bytes 0.. by pid 99"
+        );
+        assert_eq!(locks[4].kind(), LockType::Delegation);
+    }
+
+    /// Tests that the locks held on a file are listed.
+    #[cfg(feature = "sync")]
+    #[test]
+    fn locks_on_file() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        assert_eq!(locks_on(&path).unwrap(), []);
+
+        file.locks(LockBackend::Flock).lock_exclusive().unwrap();
+        let locks = locks_on(&path).unwrap();
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].kind(), LockType::Flock);
+        assert_eq!(locks[0].mode(), LockMode::Exclusive);
+        assert_eq!(locks[0].pid(), Some(std::process::id()));
+        assert!(!locks[0].is_blocked());
+        file.locks(LockBackend::Flock).unlock().unwrap();
+
+        file.locks(LockBackend::Ofd)
+            .lock_range(5, 10, LockMode::Shared)
+            .unwrap();
+        let locks = locks_on(&path).unwrap();
+        assert_eq!(locks.len(), 1);
+        assert_eq!(locks[0].kind(), LockType::Ofd);
+        assert_eq!((locks[0].start(), locks[0].end()), (5, Some(14)));
+    }
+}
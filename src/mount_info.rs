@@ -0,0 +1,443 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use crate::FsStats;
+
+/// File systems which hold no files of their own, but expose the kernel's
+/// state or devices.
+const PSEUDO_FS_TYPES: &[&str] = &[
+    "autofs",
+    "binfmt_misc",
+    "bpf",
+    "cgroup",
+    "cgroup2",
+    "configfs",
+    "debugfs",
+    "devpts",
+    "devtmpfs",
+    "efivarfs",
+    "fusectl",
+    "hugetlbfs",
+    "mqueue",
+    "nsfs",
+    "proc",
+    "pstore",
+    "rpc_pipefs",
+    "securityfs",
+    "selinuxfs",
+    "sysfs",
+    "tracefs",
+];
+
+/// A mounted file system, as listed in `/proc/self/mountinfo`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct MountInfo {
+    mount_id: u32,
+    parent_id: u32,
+    device: u64,
+    root: PathBuf,
+    mount_point: PathBuf,
+    options: Vec<String>,
+    fs_type_name: String,
+    source: String,
+    super_options: Vec<String>,
+}
+
+impl MountInfo {
+    /// Returns the ID of the mount, which may be reused once it is unmounted.
+    pub fn mount_id(&self) -> u32 {
+        self.mount_id
+    }
+
+    /// Returns the ID of the mount this one is mounted on, or of itself for
+    /// the root of the mount namespace.
+    pub fn parent_id(&self) -> u32 {
+        self.parent_id
+    }
+
+    /// Returns the ID of the device of the file system, as in
+    /// `MetadataExt::dev` for files on it.
+    pub fn device(&self) -> u64 {
+        self.device
+    }
+
+    /// Returns the directory of the file system mounted, which is `/` unless
+    /// only part of it is, as with bind mounts and btrfs subvolumes.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Returns where the file system is mounted.
+    pub fn mount_point(&self) -> &Path {
+        &self.mount_point
+    }
+
+    /// Returns the options of the mount, such as `rw` and `noatime`.
+    pub fn options(&self) -> &[String] {
+        &self.options
+    }
+
+    /// Returns the type of the file system as the kernel names it, such as
+    /// `ext4` or `nfs4`.
+    pub fn fs_type_name(&self) -> &str {
+        &self.fs_type_name
+    }
+
+    /// Returns what is mounted, such as `/dev/sda1` or `server:/export`, or a
+    /// placeholder such as `none` for file systems without a source.
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Returns the options of the file system itself, shared by every mount
+    /// of it.
+    pub fn super_options(&self) -> &[String] {
+        &self.super_options
+    }
+
+    /// Returns whether the file system is a pseudo file system, such as
+    /// `proc`, `sysfs` or `cgroup`, which exposes the kernel's state rather
+    /// than storing files.
+    pub fn is_pseudo(&self) -> bool {
+        PSEUDO_FS_TYPES.contains(&self.fs_type_name.as_str())
+    }
+}
+
+/// A mounted file system along with its stats, as listed by `mounts`.
+#[derive(Debug)]
+pub struct Mount {
+    info: MountInfo,
+    stats: Result<FsStats>,
+}
+
+impl Mount {
+    /// Returns where and how the file system is mounted.
+    pub fn info(&self) -> &MountInfo {
+        &self.info
+    }
+
+    /// Returns the stats of the file system, or the error querying them, such
+    /// as when this process may not query them or the mount went away while
+    /// mounts were listed.
+    pub fn stats(&self) -> std::result::Result<&FsStats, &Error> {
+        self.stats.as_ref()
+    }
+}
+
+/// Chooses which mounts `mounts_with` lists.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct MountFilter {
+    skip_pseudo: bool,
+    skip_fs_types: Vec<String>,
+}
+
+impl MountFilter {
+    /// Creates a filter which lists every mount.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether to skip pseudo file systems (see `MountInfo::is_pseudo`).
+    pub fn skip_pseudo(mut self, skip_pseudo: bool) -> Self {
+        self.skip_pseudo = skip_pseudo;
+        self
+    }
+
+    /// Skips file systems of the type named `fs_type_name`, such as `tmpfs` or
+    /// `overlay`.
+    pub fn skip_fs_type(mut self, fs_type_name: impl Into<String>) -> Self {
+        self.skip_fs_types.push(fs_type_name.into());
+        self
+    }
+
+    fn matches(&self, info: &MountInfo) -> bool {
+        if self.skip_pseudo && info.is_pseudo() {
+            return false;
+        }
+        !self.skip_fs_types.contains(&info.fs_type_name)
+    }
+}
+
+/// Returns the mount of the file system containing the provided path.
+///
+/// The mount is found in `/proc/self/mountinfo` by the longest mount point
+/// containing `path`, and among mounts on that mount point, by the device of
+/// `path`.
+pub fn mount_info<P>(path: P) -> Result<MountInfo>
+where
+    P: AsRef<Path>,
+{
+    let path = fs::canonicalize(path)?;
+    let device = fs::metadata(&path)?.dev();
+    let mounts = parse(&fs::read_to_string("/proc/self/mountinfo")?);
+    find_mount(mounts, &path, device)
+        .ok_or_else(|| Error::new(ErrorKind::NotFound, "no mount found for the path"))
+}
+
+/// Returns every mounted file system, in the order they were mounted, along
+/// with its stats.
+pub fn mounts() -> Result<Vec<Mount>> {
+    mounts_with(&MountFilter::new())
+}
+
+/// Returns the mounted file systems `filter` matches, in the order they were
+/// mounted, along with their stats.
+///
+/// Mounts whose stats cannot be queried, such as for lack of permission or
+/// because they were unmounted since `/proc/self/mountinfo` was read, are
+/// listed with the error (see `Mount::stats`).
+pub fn mounts_with(filter: &MountFilter) -> Result<Vec<Mount>> {
+    let mounts = parse(&fs::read_to_string("/proc/self/mountinfo")?);
+    Ok(with_stats(mounts, filter))
+}
+
+fn with_stats(mounts: Vec<MountInfo>, filter: &MountFilter) -> Vec<Mount> {
+    mounts
+        .into_iter()
+        .filter(|info| filter.matches(info))
+        .map(|info| {
+            let stats = crate::sys::statvfs(&info.mount_point);
+            Mount { info, stats }
+        })
+        .collect()
+}
+
+/// Returns the deepest mount containing `path`. Files on some file systems,
+/// such as overlayfs, report the device of another mount, so the device only
+/// chooses among mounts on the same mount point, after which later mounts hide
+/// earlier ones.
+fn find_mount(mounts: Vec<MountInfo>, path: &Path, device: u64) -> Option<MountInfo> {
+    let mut best: Option<MountInfo> = None;
+    for mount in mounts {
+        if !path.starts_with(&mount.mount_point) {
+            continue;
+        }
+        let better = match &best {
+            None => true,
+            Some(best) => {
+                let rank = |m: &MountInfo| (m.mount_point.components().count(), m.device == device);
+                rank(&mount) >= rank(best)
+            }
+        };
+        if better {
+            best = Some(mount);
+        }
+    }
+    best
+}
+
+fn parse(mountinfo: &str) -> Vec<MountInfo> {
+    mountinfo.lines().filter_map(parse_line).collect()
+}
+
+/// Parses a line such as
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`,
+/// which may have any number of optional fields before the `-`.
+fn parse_line(line: &str) -> Option<MountInfo> {
+    let mut fields = line.split(' ');
+    let mount_id = fields.next()?.parse().ok()?;
+    let parent_id = fields.next()?.parse().ok()?;
+    let (major, minor) = fields.next()?.split_once(':')?;
+    let device = rustix::fs::makedev(major.parse().ok()?, minor.parse().ok()?);
+    let root = unescape(fields.next()?);
+    let mount_point = unescape(fields.next()?);
+    let options = split_options(fields.next()?);
+    fields.find(|field| *field == "-")?;
+    let fs_type_name = fields.next()?.to_owned();
+    let source = unescape(fields.next()?)
+        .into_os_string()
+        .into_string()
+        .ok()?;
+    let super_options = split_options(fields.next()?);
+    Some(MountInfo {
+        mount_id,
+        parent_id,
+        device,
+        root,
+        mount_point,
+        options,
+        fs_type_name,
+        source,
+        super_options,
+    })
+}
+
+fn split_options(options: &str) -> Vec<String> {
+    options.split(',').map(str::to_owned).collect()
+}
+
+/// Undoes the octal escapes of spaces, tabs, newlines and backslashes in
+/// paths.
+fn unescape(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut path = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escape = bytes.get(i + 1..i + 4).filter(|_| bytes[i] == b'\\');
+        match escape.and_then(|octal| u8::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok())
+        {
+            Some(byte) => {
+                path.push(byte);
+                i += 4;
+            }
+            None => {
+                path.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    PathBuf::from(OsString::from_vec(path))
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use super::*;
+
+    /// Tests parsing of `/proc/self/mountinfo` lines.
+    #[test]
+    fn parse_mountinfo() {
+        let mounts = parse(
+            "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw,errors=remount-ro\n\
+             36 22 0:31 /data /mnt/my\\040data rw,noatime master:1 shared:2 - nfs4 server:/export rw,vers=4.2\n\
+             40 22 0:5 / /proc rw,nosuid,nodev,noexec - proc proc rw\n\
+             garbage\n",
+        );
+        assert_eq!(mounts.len(), 3);
+        assert_eq!(
+            mounts[1],
+            MountInfo {
+                mount_id: 36,
+                parent_id: 22,
+                device: rustix::fs::makedev(0, 31),
+                root: PathBuf::from("/data"),
+                mount_point: PathBuf::from("/mnt/my data"),
+                options: vec!["rw".to_owned(), "noatime".to_owned()],
+                fs_type_name: "nfs4".to_owned(),
+                source: "server:/export".to_owned(),
+                super_options: vec!["rw".to_owned(), "vers=4.2".to_owned()],
+            }
+        );
+        assert_eq!(mounts[2].fs_type_name(), "proc");
+
+        let root = find_mount(
+            mounts.clone(),
+            Path::new("/home/user"),
+            rustix::fs::makedev(8, 1),
+        )
+        .unwrap();
+        assert_eq!(root.mount_id(), 22);
+        let nfs = find_mount(mounts.clone(), Path::new("/mnt/my data/file"), 0).unwrap();
+        assert_eq!(nfs.mount_id(), 36);
+        assert_eq!(find_mount(mounts, Path::new("relative"), 0), None);
+    }
+
+    /// Tests that the deepest mount is found even when files on it report the
+    /// device of a shallower mount, as on overlayfs.
+    #[test]
+    fn find_deepest_mount() {
+        let mounts = parse(
+            "22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw\n\
+             60 22 0:50 / /var/lib/docker/overlay2/merged rw - overlay overlay rw,lowerdir=/l\n",
+        );
+        let device = rustix::fs::makedev(8, 1);
+        let overlay = find_mount(
+            mounts.clone(),
+            Path::new("/var/lib/docker/overlay2/merged/etc"),
+            device,
+        )
+        .unwrap();
+        assert_eq!(overlay.mount_id(), 60);
+
+        // Of mounts on the same mount point, the one of the device is found.
+        let mounts = parse(
+            "22 1 8:1 / / rw - ext4 /dev/sda1 rw\n\
+             70 22 8:2 / /mnt rw - ext4 /dev/sda2 rw\n\
+             71 22 0:60 / /mnt rw - tmpfs tmpfs rw\n",
+        );
+        let mnt = find_mount(mounts, Path::new("/mnt/file"), rustix::fs::makedev(8, 2)).unwrap();
+        assert_eq!(mnt.mount_id(), 70);
+    }
+
+    /// Tests that the mount of a directory is found.
+    #[test]
+    fn mount_info_of_dir() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let mount = mount_info(tempdir.path()).unwrap();
+        assert!(fs::canonicalize(tempdir.path())
+            .unwrap()
+            .starts_with(mount.mount_point()));
+
+        assert_eq!(mount_info("/proc/self").unwrap().fs_type_name(), "proc");
+        assert_eq!(
+            mount_info(tempdir.path().join("missing"))
+                .unwrap_err()
+                .kind(),
+            ErrorKind::NotFound
+        );
+    }
+
+    /// Tests that the mounts of this process are listed with their stats, and
+    /// filtered.
+    #[test]
+    fn list_mounts() {
+        let all = mounts().unwrap();
+        assert_eq!(
+            all.len(),
+            parse(&fs::read_to_string("/proc/self/mountinfo").unwrap()).len()
+        );
+        let proc = all
+            .iter()
+            .find(|mount| mount.info().mount_point() == Path::new("/proc"))
+            .unwrap();
+        assert!(proc.info().is_pseudo());
+        assert!(proc.stats().is_ok());
+
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let own = mount_info(tempdir.path()).unwrap();
+        let real = mounts_with(&MountFilter::new().skip_pseudo(true)).unwrap();
+        assert!(real.iter().all(|mount| !mount.info().is_pseudo()));
+        let mount = real.iter().find(|mount| *mount.info() == own).unwrap();
+        assert_eq!(
+            mount.stats().unwrap().total_space(),
+            crate::total_space(tempdir.path()).unwrap()
+        );
+
+        let filter = MountFilter::new().skip_fs_type(own.fs_type_name());
+        assert!(mounts_with(&filter)
+            .unwrap()
+            .iter()
+            .all(|mount| mount.info().fs_type_name() != own.fs_type_name()));
+    }
+
+    /// Tests that mounts which cannot be queried are listed with the error,
+    /// whatever it is.
+    #[test]
+    fn mounts_without_stats() {
+        let mounts = parse("50 22 0:99 / /nonexistent/fs4 rw - tmpfs tmpfs rw\n");
+        let mounts = with_stats(mounts, &MountFilter::new());
+        assert_eq!(mounts.len(), 1);
+        assert_eq!(mounts[0].stats().unwrap_err().kind(), ErrorKind::NotFound);
+
+        // A mount point under a regular file fails with `ENOTDIR`.
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let file = tempdir.path().join("fs4");
+        fs::write(&file, b"").unwrap();
+        let mount_point = file.join("mnt");
+        let line = format!(
+            "51 22 0:99 / {} rw - tmpfs tmpfs rw\n",
+            mount_point.display()
+        );
+        let mounts = with_stats(parse(&line), &MountFilter::new());
+        assert_eq!(mounts[0].info().mount_point(), mount_point);
+        assert_eq!(
+            mounts[0].stats().unwrap_err().raw_os_error(),
+            Some(libc::ENOTDIR)
+        );
+    }
+}
@@ -0,0 +1,8 @@
+cfg_sync!(
+    mod sync_impl;
+    pub use sync_impl::{NamedRwLock, NamedRwLockGuard};
+);
+
+cfg_async!(
+    pub(crate) mod async_impl;
+);
@@ -0,0 +1,123 @@
+macro_rules! async_named_lock {
+    ($file: ty) => {
+        use std::io::Result;
+        use std::marker::PhantomData;
+        use std::path::{Path, PathBuf};
+
+        use crate::{Exclusive, LockKind, LockMode, Shared};
+
+        /// A reader-writer lock shared between processes, named by the path of its
+        /// lock file.
+        ///
+        /// The lock file, and its parent directory, are created when the lock is first
+        /// taken, and are left in place when it is released. Each guard opens the lock
+        /// file anew, so guards from the same process exclude each other with the
+        /// `flock` and OFD backends, but not with POSIX record locks, which are owned
+        /// by the process.
+        #[derive(Clone, Debug)]
+        pub struct NamedRwLock {
+            path: PathBuf,
+        }
+
+        impl NamedRwLock {
+            /// Creates a lock named by the lock file at `path`, without touching the
+            /// filesystem.
+            pub fn new<P>(path: P) -> Self
+            where
+                P: Into<PathBuf>,
+            {
+                Self { path: path.into() }
+            }
+
+            /// Returns the path of the lock file.
+            pub fn path(&self) -> &Path {
+                &self.path
+            }
+
+            /// Locks for reading, waiting while a writer holds the lock.
+            pub async fn read(&self) -> Result<NamedRwLockGuard<Shared>> {
+                let file = open(&self.path).await?;
+                AsyncFileExt::lock_shared(&file).await?;
+                Ok(NamedRwLockGuard::new(file))
+            }
+
+            /// Locks for writing, waiting while a reader or writer holds the lock.
+            pub async fn write(&self) -> Result<NamedRwLockGuard<Exclusive>> {
+                let file = open(&self.path).await?;
+                AsyncFileExt::lock_exclusive(&file).await?;
+                Ok(NamedRwLockGuard::new(file))
+            }
+
+            /// Locks for reading, or returns an error if a writer holds the lock (see
+            /// `lock_contended_error`).
+            pub async fn try_read(&self) -> Result<NamedRwLockGuard<Shared>> {
+                self.try_lock().await
+            }
+
+            /// Locks for writing, or returns an error if a reader or writer holds the
+            /// lock (see `lock_contended_error`).
+            pub async fn try_write(&self) -> Result<NamedRwLockGuard<Exclusive>> {
+                self.try_lock().await
+            }
+
+            async fn try_lock<M: LockKind>(&self) -> Result<NamedRwLockGuard<M>> {
+                let file = open(&self.path).await?;
+                match M::MODE {
+                    LockMode::Shared => AsyncFileExt::try_lock_shared(&file)?,
+                    LockMode::Exclusive => AsyncFileExt::try_lock_exclusive(&file)?,
+                }
+                Ok(NamedRwLockGuard::new(file))
+            }
+        }
+
+        /// A [`NamedRwLock`] held for reading ([`Shared`](crate::Shared)) or writing
+        /// ([`Exclusive`](crate::Exclusive)), released when the guard is dropped.
+        ///
+        /// The guard owns the open lock file, so it may outlive the `NamedRwLock`.
+        /// Errors from the implicit unlock on drop are ignored; use
+        /// [`NamedRwLockGuard::unlock`] to observe them.
+        #[derive(Debug)]
+        #[must_use = "the lock is released as soon as the guard is dropped"]
+        pub struct NamedRwLockGuard<M: LockKind> {
+            /// The locked file, taken out by `unlock`.
+            file: Option<$file>,
+            mode: PhantomData<fn() -> M>,
+        }
+
+        impl<M: LockKind> NamedRwLockGuard<M> {
+            fn new(file: $file) -> Self {
+                Self {
+                    file: Some(file),
+                    mode: PhantomData,
+                }
+            }
+
+            /// Unlocks the lock, returning any error reported by the operating system.
+            pub fn unlock(mut self) -> Result<()> {
+                self.file
+                    .take()
+                    .map_or(Ok(()), |file| AsyncFileExt::unlock(&file))
+            }
+        }
+
+        impl<M: LockKind> Drop for NamedRwLockGuard<M> {
+            fn drop(&mut self) {
+                if let Some(file) = &self.file {
+                    let _ = AsyncFileExt::unlock(file);
+                }
+            }
+        }
+    };
+}
+
+cfg_async_std! {
+    pub(crate) mod async_std_impl;
+}
+
+cfg_smol! {
+    pub(crate) mod smol_impl;
+}
+
+cfg_tokio! {
+    pub(crate) mod tokio_impl;
+}
@@ -0,0 +1,53 @@
+use tokio::fs::{self, File};
+
+use crate::file_ext::async_impl::tokio_impl::AsyncFileExt;
+
+async_named_lock!(File);
+
+async fn open(path: &Path) -> Result<File> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent).await?;
+    }
+    fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+        .await
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use crate::{lock_contended_error, tokio::NamedRwLock};
+
+    /// Tests that readers share a named lock and writers exclude everyone.
+    #[tokio::test]
+    async fn named_rw_lock() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let lock = NamedRwLock::new(tempdir.path().join("locks").join("fs4.lock"));
+
+        let reader = lock.read().await.unwrap();
+        assert!(lock.path().exists());
+        let other_reader = lock.try_read().await.unwrap();
+        assert_eq!(
+            lock.try_write().await.unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        drop(reader);
+        other_reader.unlock().unwrap();
+
+        let writer = lock.write().await.unwrap();
+        assert_eq!(
+            lock.try_read().await.unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        drop(writer);
+        lock.try_write().await.unwrap().unlock().unwrap();
+    }
+}
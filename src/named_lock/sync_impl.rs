@@ -0,0 +1,159 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Result;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use crate::{Exclusive, FileExt, LockKind, LockMode, Shared};
+
+/// A reader-writer lock shared between processes, named by the path of its
+/// lock file.
+///
+/// The lock file, and its parent directory, are created when the lock is first
+/// taken, and are left in place when it is released. Each guard opens the lock
+/// file anew, so guards from the same process exclude each other with the
+/// `flock` and OFD backends, but not with POSIX record locks, which are owned
+/// by the process.
+#[derive(Clone, Debug)]
+pub struct NamedRwLock {
+    path: PathBuf,
+}
+
+impl NamedRwLock {
+    /// Creates a lock named by the lock file at `path`, without touching the
+    /// filesystem.
+    pub fn new<P>(path: P) -> Self
+    where
+        P: Into<PathBuf>,
+    {
+        Self { path: path.into() }
+    }
+
+    /// Returns the path of the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Locks for reading, blocking while a writer holds the lock.
+    pub fn read(&self) -> Result<NamedRwLockGuard<Shared>> {
+        self.lock(true)
+    }
+
+    /// Locks for writing, blocking while a reader or writer holds the lock.
+    pub fn write(&self) -> Result<NamedRwLockGuard<Exclusive>> {
+        self.lock(true)
+    }
+
+    /// Locks for reading, or returns an error if a writer holds the lock (see
+    /// `lock_contended_error`).
+    pub fn try_read(&self) -> Result<NamedRwLockGuard<Shared>> {
+        self.lock(false)
+    }
+
+    /// Locks for writing, or returns an error if a reader or writer holds the
+    /// lock (see `lock_contended_error`).
+    pub fn try_write(&self) -> Result<NamedRwLockGuard<Exclusive>> {
+        self.lock(false)
+    }
+
+    fn lock<M: LockKind>(&self, wait: bool) -> Result<NamedRwLockGuard<M>> {
+        let file = open(&self.path)?;
+        match (M::MODE, wait) {
+            (LockMode::Shared, true) => FileExt::lock_shared(&file)?,
+            (LockMode::Exclusive, true) => FileExt::lock_exclusive(&file)?,
+            (LockMode::Shared, false) => FileExt::try_lock_shared(&file)?,
+            (LockMode::Exclusive, false) => FileExt::try_lock_exclusive(&file)?,
+        }
+        Ok(NamedRwLockGuard {
+            file: Some(file),
+            mode: PhantomData,
+        })
+    }
+}
+
+fn open(path: &Path) -> Result<File> {
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        fs::create_dir_all(parent)?;
+    }
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(path)
+}
+
+/// A [`NamedRwLock`] held for reading ([`Shared`]) or writing ([`Exclusive`]),
+/// released when the guard is dropped.
+///
+/// The guard owns the open lock file, so it may outlive the `NamedRwLock`.
+/// Errors from the implicit unlock on drop are ignored; use
+/// [`NamedRwLockGuard::unlock`] to observe them.
+#[derive(Debug)]
+#[must_use = "the lock is released as soon as the guard is dropped"]
+pub struct NamedRwLockGuard<M: LockKind> {
+    /// The locked file, taken out by `unlock`.
+    file: Option<File>,
+    mode: PhantomData<M>,
+}
+
+impl<M: LockKind> NamedRwLockGuard<M> {
+    /// Unlocks the lock, returning any error reported by the operating system.
+    pub fn unlock(mut self) -> Result<()> {
+        self.file
+            .take()
+            .map_or(Ok(()), |file| FileExt::unlock(&file))
+    }
+}
+
+impl<M: LockKind> Drop for NamedRwLockGuard<M> {
+    fn drop(&mut self) {
+        if let Some(file) = &self.file {
+            let _ = FileExt::unlock(file);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use super::*;
+    use crate::lock_contended_error;
+
+    /// Tests that readers share a named lock and writers exclude everyone.
+    #[test]
+    fn named_rw_lock() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let lock = NamedRwLock::new(tempdir.path().join("locks").join("fs4.lock"));
+
+        let reader = lock.read().unwrap();
+        assert!(lock.path().exists());
+        let other_reader = lock.try_read().unwrap();
+        assert_eq!(
+            lock.try_write().unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        drop(reader);
+        other_reader.unlock().unwrap();
+
+        let writer = lock.write().unwrap();
+        assert_eq!(
+            lock.try_read().unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        assert_eq!(
+            lock.try_write().unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        // The guard is owned, so it outlives the lock which created it.
+        drop(lock);
+        let lock = NamedRwLock::new(tempdir.path().join("locks").join("fs4.lock"));
+        assert!(lock.try_read().is_err());
+        drop(writer);
+        lock.try_write().unwrap().unlock().unwrap();
+    }
+}
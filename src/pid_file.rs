@@ -0,0 +1,375 @@
+use std::error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+#[cfg(unix)]
+use crate::unix::sync_impl as sys;
+#[cfg(windows)]
+use crate::windows::sync_impl as sys;
+use crate::{lock_contended_error, FileExt, LockMode};
+
+/// A PID file: a file holding the ID of the process which has it locked, used
+/// to make sure that only one instance of a daemon runs at a time.
+///
+/// The file stays locked exclusively for as long as the `PidFile` exists, and
+/// is removed when it is dropped. The lock, not the presence of the file, is
+/// what marks it as held: a PID file left behind by a process which exited
+/// without removing it is unlocked, and is taken over by the next
+/// [`PidFile::create`].
+///
+/// On Unix the file is locked with `flock`, regardless of the default
+/// [`LockBackend`](crate::LockBackend), so it can be checked with `flock(1)`. On
+/// Windows a byte far past the end of the file is locked instead of the whole
+/// file, so that the PID can still be read by other processes.
+#[derive(Debug)]
+pub struct PidFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Creates the PID file at `path` if it does not exist, locks it, and writes
+    /// the ID of the current process to it.
+    ///
+    /// If another process holds the PID file, this fails with an error of the
+    /// same kind as `lock_contended_error`, whose message names the process.
+    /// A lock without a live process ID in the file may be held by a process
+    /// which has not written its ID yet, or, on platforms other than Linux, by
+    /// [`PidFile::holder`] or [`PidFile::is_stale`] probing the file, so the
+    /// lock is tried again for a short while before giving up.
+    pub fn create<P>(path: P) -> Result<Self>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let mut retries = 0;
+        loop {
+            // Truncating here would erase the PID of a live holder.
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)?;
+            if let Err(err) = try_lock(&file, LockMode::Exclusive) {
+                if err.raw_os_error() != lock_contended_error().raw_os_error() {
+                    return Err(err);
+                }
+                let pid = read_pid(&mut file)?;
+                let live = match pid {
+                    Some(pid) => crate::sys::process_exists(pid)?,
+                    None => false,
+                };
+                if !live && retries < CONTENDED_RETRIES {
+                    retries += 1;
+                    std::thread::sleep(CONTENDED_RETRY_DELAY);
+                    continue;
+                }
+                let holder = match pid {
+                    Some(pid) => format!("process {}", pid),
+                    None => "another process".to_owned(),
+                };
+                let msg = format!("{} is locked by {}", path.display(), holder);
+                return Err(Error::new(err.kind(), Held { msg, source: err }));
+            }
+
+            // The previous holder may have removed the file between it being
+            // opened and locked here, in which case the lock is worthless.
+            if is_same_file(&file, path)? {
+                write_pid(&mut file)?;
+                return Ok(Self {
+                    file,
+                    path: path.to_owned(),
+                });
+            }
+        }
+    }
+
+    /// Returns the path of the PID file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the ID of the process holding the PID file at `path`, or `None`
+    /// if it does not exist, is not locked, or the holder has not written its
+    /// ID yet.
+    pub fn holder<P>(path: P) -> Result<Option<u32>>
+    where
+        P: AsRef<Path>,
+    {
+        let Some(mut file) = open_existing(path.as_ref())? else {
+            return Ok(None);
+        };
+        if is_locked(&file)? {
+            read_pid(&mut file)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns whether the PID file at `path` is stale: it exists and is not
+    /// locked, and the process whose ID it holds no longer exists.
+    pub fn is_stale<P>(path: P) -> Result<bool>
+    where
+        P: AsRef<Path>,
+    {
+        let Some(mut file) = open_existing(path.as_ref())? else {
+            return Ok(false);
+        };
+        if is_locked(&file)? {
+            return Ok(false);
+        }
+        match read_pid(&mut file)? {
+            Some(pid) => crate::sys::process_exists(pid).map(|exists| !exists),
+            None => Ok(true),
+        }
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // Another process may have replaced the file after finding it stale.
+        if is_same_file(&self.file, &self.path).unwrap_or(false) {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// The error `create` fails with when another process holds the PID file. The
+/// error of the lock attempt is kept as its source, so that `classify` still
+/// tells it was contended.
+#[derive(Debug)]
+struct Held {
+    msg: String,
+    source: Error,
+}
+
+impl fmt::Display for Held {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.msg)
+    }
+}
+
+impl error::Error for Held {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// How many times `create` tries again to lock a PID file without a live
+/// holder, and how long it waits in between.
+const CONTENDED_RETRIES: u32 = 10;
+const CONTENDED_RETRY_DELAY: Duration = Duration::from_millis(10);
+
+/// Returns whether the PID file open as `file` is locked. On Linux the lock is
+/// looked up in `/proc/locks`, so the probe cannot make a concurrent `create`
+/// fail.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn is_locked(file: &File) -> Result<bool> {
+    let status = file.locks(crate::LockBackend::Flock).lock_status()?;
+    Ok(status.is_locked())
+}
+
+/// Returns whether the PID file open as `file` is locked. The lock cannot be
+/// queried here, so it is taken shared and released again.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn is_locked(file: &File) -> Result<bool> {
+    match try_lock(file, LockMode::Shared) {
+        Ok(()) => unlock(file).map(|_| false),
+        Err(err) if err.raw_os_error() == lock_contended_error().raw_os_error() => Ok(true),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(unix)]
+fn try_lock(file: &File, mode: LockMode) -> Result<()> {
+    let locks = file.locks(crate::LockBackend::Flock);
+    match mode {
+        LockMode::Shared => locks.try_lock_shared(),
+        LockMode::Exclusive => locks.try_lock_exclusive(),
+    }
+}
+
+#[cfg(all(unix, not(any(target_os = "linux", target_os = "android"))))]
+fn unlock(file: &File) -> Result<()> {
+    file.locks(crate::LockBackend::Flock).unlock()
+}
+
+/// Windows locks are mandatory, so the PID itself must stay unlocked.
+#[cfg(windows)]
+const LOCK_OFFSET: u64 = u64::MAX - 1;
+
+#[cfg(windows)]
+fn try_lock(file: &File, mode: LockMode) -> Result<()> {
+    file.try_lock_range(LOCK_OFFSET, 1, mode)
+}
+
+#[cfg(windows)]
+fn unlock(file: &File) -> Result<()> {
+    file.unlock_range(LOCK_OFFSET, 1)
+}
+
+fn open_existing(path: &Path) -> Result<Option<File>> {
+    match File::open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+fn is_same_file(file: &File, path: &Path) -> Result<bool> {
+    match open_existing(path)? {
+        Some(other) => Ok(sys::file_id(file)? == sys::file_id(&other)?),
+        None => Ok(false),
+    }
+}
+
+/// Writes the ID of the current process over the start of the file before
+/// truncating it, so that readers never see an empty file.
+fn write_pid(file: &mut File) -> Result<()> {
+    let pid = format!("{}\n", std::process::id());
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(pid.as_bytes())?;
+    file.set_len(pid.len() as u64)?;
+    file.sync_all()
+}
+
+fn read_pid(file: &mut File) -> Result<Option<u32>> {
+    let mut contents = Vec::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.take(64).read_to_end(&mut contents)?;
+    let pid = String::from_utf8_lossy(&contents)
+        .lines()
+        .next()
+        .and_then(|line| line.trim().parse().ok());
+    Ok(pid)
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    const CHILD_ENV: &str = "FS4_PID_FILE";
+
+    /// Holds the PID file named by `FS4_PID_FILE` until killed. Run as a child
+    /// process by the other tests.
+    #[test]
+    #[ignore]
+    fn pid_file_child() {
+        let Some(path) = std::env::var_os(CHILD_ENV) else {
+            return;
+        };
+        let _pid_file = PidFile::create(path).unwrap();
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    }
+
+    fn spawn_holder(path: &Path) -> std::process::Child {
+        let child = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "pid_file::test::pid_file_child",
+                "--ignored",
+                "--nocapture",
+            ])
+            .env(CHILD_ENV, path)
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap();
+        let start = Instant::now();
+        while PidFile::holder(path).unwrap() != Some(child.id()) {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "child never locked the PID file"
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        child
+    }
+
+    /// Tests that a PID file held by another process is reported, and taken
+    /// over once that process dies.
+    #[test]
+    fn pid_file_contended() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4.pid");
+
+        let mut child = spawn_holder(&path);
+        let err = PidFile::create(&path).unwrap_err();
+        assert_eq!(err.kind(), lock_contended_error().kind());
+        assert!(
+            err.to_string().contains(&format!("process {}", child.id())),
+            "{}",
+            err
+        );
+        assert!(crate::classify(&err).is_contended());
+        assert!(!PidFile::is_stale(&path).unwrap());
+
+        // A killed process leaves its PID file behind, unlocked.
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(PidFile::holder(&path).unwrap(), None);
+        assert!(PidFile::is_stale(&path).unwrap());
+
+        let pid_file = PidFile::create(&path).unwrap();
+        assert_eq!(PidFile::holder(&path).unwrap(), Some(std::process::id()));
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            format!("{}\n", std::process::id())
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+
+    /// Tests that probing a PID file never makes a concurrent `create` fail.
+    #[test]
+    fn pid_file_probed() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4.pid");
+        let done = std::sync::atomic::AtomicBool::new(false);
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                while !done.load(std::sync::atomic::Ordering::Relaxed) {
+                    PidFile::holder(&path).unwrap();
+                    PidFile::is_stale(&path).unwrap();
+                }
+            });
+            for _ in 0..200 {
+                drop(PidFile::create(&path).unwrap());
+            }
+            done.store(true, std::sync::atomic::Ordering::Relaxed);
+        });
+    }
+
+    /// Tests that a PID file is not stale while its process runs, even if it is
+    /// not locked.
+    #[test]
+    fn pid_file_stale() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4.pid");
+        assert_eq!(PidFile::holder(&path).unwrap(), None);
+        assert!(!PidFile::is_stale(&path).unwrap());
+
+        fs::write(&path, format!("{}\n", std::process::id())).unwrap();
+        assert!(!PidFile::is_stale(&path).unwrap());
+        fs::write(&path, "not a pid\n").unwrap();
+        assert!(PidFile::is_stale(&path).unwrap());
+
+        // Dropping a PID file which was replaced leaves the replacement alone.
+        let pid_file = PidFile::create(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        fs::write(&path, "replacement").unwrap();
+        drop(pid_file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "replacement");
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::Result;
+use std::marker::PhantomData;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+
+#[cfg(unix)]
+use crate::unix::sync_impl as sys;
+#[cfg(windows)]
+use crate::windows::sync_impl as sys;
+use crate::{lock_contended_error, Exclusive, FileExt, LockKind, LockMode, Shared};
+
+/// The locks in use by this process, by file ID. An entry lives for as long as
+/// a `ProcessLock` or guard refers to it.
+static REGISTRY: Mutex<BTreeMap<(u64, u64), Weak<Entry>>> = Mutex::new(BTreeMap::new());
+
+/// A reader-writer lock on a file which excludes both other threads of this
+/// process and other processes.
+///
+/// How a file lock taken through one `File` interacts with one taken through
+/// another `File` in the same process depends on the lock backend: `flock`
+/// and OFD locks conflict, while POSIX record locks do not. A `ProcessLock`
+/// gives the same behaviour everywhere: every `ProcessLock` on a file, through
+/// whichever `File` it was created, shares one in-memory reader-writer lock,
+/// and the file is locked with `FileExt` while that lock is held, in shared
+/// mode for readers and exclusive mode for a writer.
+///
+/// The file lock is taken through a duplicate of the `File` the first
+/// `ProcessLock` on the file was created with. With POSIX record locks,
+/// closing any descriptor of the file releases it, so the files passed to
+/// `ProcessLock::new` should stay open while the lock is held.
+#[derive(Debug)]
+pub struct ProcessLock {
+    entry: Arc<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    file: File,
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    readers: usize,
+    writer: bool,
+    /// Whether a thread is taking the file lock for the first reader.
+    pending: bool,
+}
+
+impl State {
+    fn can_read(&self) -> bool {
+        !self.writer && !self.pending
+    }
+
+    fn can_write(&self) -> bool {
+        self.can_read() && self.readers == 0
+    }
+}
+
+impl ProcessLock {
+    /// Returns the lock shared by every `ProcessLock` on the same file as
+    /// `file`.
+    pub fn new(file: &File) -> Result<Self> {
+        let id = sys::file_id(file)?;
+        let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(entry) = registry.get(&id).and_then(Weak::upgrade) {
+            return Ok(Self { entry });
+        }
+        let entry = Arc::new(Entry {
+            file: file.try_clone()?,
+            state: Mutex::default(),
+            changed: Condvar::new(),
+        });
+        registry.retain(|_, entry| entry.strong_count() > 0);
+        registry.insert(id, Arc::downgrade(&entry));
+        Ok(Self { entry })
+    }
+
+    /// Locks for reading, blocking while a writer in this or another process
+    /// holds the lock.
+    pub fn read(&self) -> Result<ProcessLockGuard<Shared>> {
+        self.lock_shared(true)
+    }
+
+    /// Locks for writing, blocking while a reader or writer in this or another
+    /// process holds the lock.
+    pub fn write(&self) -> Result<ProcessLockGuard<Exclusive>> {
+        self.lock_exclusive(true)
+    }
+
+    /// Locks for reading, or returns an error if a writer in this or another
+    /// process holds the lock (see `lock_contended_error`).
+    pub fn try_read(&self) -> Result<ProcessLockGuard<Shared>> {
+        self.lock_shared(false)
+    }
+
+    /// Locks for writing, or returns an error if a reader or writer in this or
+    /// another process holds the lock (see `lock_contended_error`).
+    pub fn try_write(&self) -> Result<ProcessLockGuard<Exclusive>> {
+        self.lock_exclusive(false)
+    }
+
+    fn lock_shared(&self, wait: bool) -> Result<ProcessLockGuard<Shared>> {
+        let entry = &self.entry;
+        let mut state = entry.wait(wait, State::can_read)?;
+        if state.readers == 0 {
+            // Other readers wait for the file lock rather than assume it.
+            state.pending = true;
+            drop(state);
+            let locked = if wait {
+                FileExt::lock_shared(&entry.file)
+            } else {
+                FileExt::try_lock_shared(&entry.file)
+            };
+            state = entry.state();
+            state.pending = false;
+            if let Err(err) = locked {
+                entry.changed.notify_all();
+                return Err(err);
+            }
+        }
+        state.readers += 1;
+        entry.changed.notify_all();
+        Ok(self.guard())
+    }
+
+    fn lock_exclusive(&self, wait: bool) -> Result<ProcessLockGuard<Exclusive>> {
+        let entry = &self.entry;
+        let mut state = entry.wait(wait, State::can_write)?;
+        state.writer = true;
+        drop(state);
+        let locked = if wait {
+            FileExt::lock_exclusive(&entry.file)
+        } else {
+            FileExt::try_lock_exclusive(&entry.file)
+        };
+        if let Err(err) = locked {
+            entry.state().writer = false;
+            entry.changed.notify_all();
+            return Err(err);
+        }
+        Ok(self.guard())
+    }
+
+    fn guard<M: LockKind>(&self) -> ProcessLockGuard<M> {
+        ProcessLockGuard {
+            entry: self.entry.clone(),
+            mode: PhantomData,
+        }
+    }
+}
+
+impl Entry {
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Waits until `ready` holds, or fails if it does not and `wait` is false.
+    fn wait(&self, wait: bool, ready: fn(&State) -> bool) -> Result<MutexGuard<'_, State>> {
+        let mut state = self.state();
+        while !ready(&state) {
+            if !wait {
+                return Err(lock_contended_error());
+            }
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        Ok(state)
+    }
+}
+
+/// A [`ProcessLock`] held for reading ([`Shared`]) or writing ([`Exclusive`]),
+/// released when the guard is dropped.
+///
+/// The guard may outlive the `ProcessLock` which created it. Errors from
+/// unlocking the file are ignored.
+#[derive(Debug)]
+#[must_use = "the lock is released as soon as the guard is dropped"]
+pub struct ProcessLockGuard<M: LockKind> {
+    entry: Arc<Entry>,
+    mode: PhantomData<M>,
+}
+
+impl<M: LockKind> Drop for ProcessLockGuard<M> {
+    fn drop(&mut self) {
+        let entry = &self.entry;
+        let mut state = entry.state();
+        match M::MODE {
+            LockMode::Shared => {
+                state.readers -= 1;
+                if state.readers == 0 {
+                    let _ = FileExt::unlock(&entry.file);
+                }
+            }
+            LockMode::Exclusive => {
+                let _ = FileExt::unlock(&entry.file);
+                state.writer = false;
+            }
+        }
+        entry.changed.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate tempdir;
+
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+    use std::thread;
+
+    use super::*;
+
+    /// Tests that threads locking the same file through their own `File`s
+    /// exclude each other.
+    #[test]
+    fn process_lock_threads() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        fs::write(&path, b"").unwrap();
+
+        const THREADS: usize = 16;
+        let barrier = Barrier::new(THREADS);
+        let writers = AtomicUsize::new(0);
+        let readers = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for i in 0..THREADS {
+                let (path, barrier, writers, readers) = (&path, &barrier, &writers, &readers);
+                scope.spawn(move || {
+                    let file = fs::File::open(path).unwrap();
+                    let lock = ProcessLock::new(&file).unwrap();
+                    barrier.wait();
+                    for _ in 0..50 {
+                        if i % 4 == 0 {
+                            let _guard = lock.write().unwrap();
+                            assert_eq!(writers.fetch_add(1, Ordering::SeqCst), 0);
+                            assert_eq!(readers.load(Ordering::SeqCst), 0);
+                            thread::yield_now();
+                            writers.fetch_sub(1, Ordering::SeqCst);
+                        } else {
+                            let _guard = lock.read().unwrap();
+                            readers.fetch_add(1, Ordering::SeqCst);
+                            assert_eq!(writers.load(Ordering::SeqCst), 0);
+                            thread::yield_now();
+                            readers.fetch_sub(1, Ordering::SeqCst);
+                        }
+                    }
+                });
+            }
+        });
+
+        // The file is unlocked once every guard is dropped.
+        FileExt::try_lock_exclusive(&fs::File::open(&path).unwrap()).unwrap();
+    }
+
+    /// Tests that the try methods fail on contention within the process, and
+    /// that the file itself is locked while the lock is held.
+    #[test]
+    fn process_lock_try() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        fs::write(&path, b"").unwrap();
+        let first = ProcessLock::new(&fs::File::open(&path).unwrap()).unwrap();
+        let second = ProcessLock::new(&fs::File::open(&path).unwrap()).unwrap();
+
+        let reader = first.read().unwrap();
+        let other_reader = second.try_read().unwrap();
+        assert_eq!(
+            second.try_write().unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        let outsider = fs::File::open(&path).unwrap();
+        assert_eq!(
+            FileExt::try_lock_exclusive(&outsider)
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        FileExt::try_lock_shared(&outsider).unwrap();
+        FileExt::unlock(&outsider).unwrap();
+        drop(reader);
+        drop(other_reader);
+
+        let writer = second.try_write().unwrap();
+        drop(second);
+        assert_eq!(
+            first.try_read().unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        assert_eq!(
+            FileExt::try_lock_shared(&outsider)
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        drop(writer);
+        drop(first.try_write().unwrap());
+    }
+}
@@ -0,0 +1,250 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{Error, ErrorKind};
+use std::time::Duration;
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+use std::{io::Result, time::Instant};
+
+/// Decides how long to wait between attempts to acquire a contended lock.
+///
+/// The deadline and timeout lock methods poll the non-blocking `try_lock_*`
+/// methods, sleeping for the delay returned by the policy after every attempt
+/// that fails with `lock_contended_error`.
+pub trait RetryPolicy {
+    /// Returns the delay before the next attempt. `attempt` is the number of
+    /// attempts made so far, starting at 1.
+    fn delay(&mut self, attempt: u32) -> Duration;
+}
+
+/// Retries at a fixed interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct FixedInterval {
+    interval: Duration,
+}
+
+impl FixedInterval {
+    /// Creates a policy which waits `interval` between attempts.
+    pub fn new(interval: Duration) -> Self {
+        Self { interval }
+    }
+}
+
+impl RetryPolicy for FixedInterval {
+    fn delay(&mut self, _attempt: u32) -> Duration {
+        self.interval
+    }
+}
+
+/// Retries with exponentially growing delays, with random jitter so that
+/// competing waiters do not retry in lockstep.
+///
+/// The delay before attempt `n + 1` is drawn uniformly from the upper half of
+/// `min(initial * 2^(n - 1), max)`.
+#[derive(Clone, Debug)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    max: Duration,
+    seed: u64,
+}
+
+impl ExponentialBackoff {
+    /// Creates a policy which starts waiting `initial` and doubles the delay
+    /// after every attempt, up to `max`.
+    pub fn new(initial: Duration, max: Duration) -> Self {
+        Self {
+            initial,
+            max,
+            seed: RandomState::new().build_hasher().finish() | 1,
+        }
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        self.seed
+    }
+}
+
+impl Default for ExponentialBackoff {
+    /// Starts at 1ms and backs off up to 100ms.
+    fn default() -> Self {
+        Self::new(Duration::from_millis(1), Duration::from_millis(100))
+    }
+}
+
+impl RetryPolicy for ExponentialBackoff {
+    fn delay(&mut self, attempt: u32) -> Duration {
+        let factor = 1u32
+            .checked_shl(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let delay = self.initial.saturating_mul(factor).min(self.max);
+        let nanos = delay.as_nanos().min(u64::MAX as u128) as u64;
+        let half = nanos / 2;
+        Duration::from_nanos(half + self.next_random() % (nanos - half + 1))
+    }
+}
+
+pub(crate) fn timed_out_error() -> Error {
+    Error::new(ErrorKind::TimedOut, "timed out waiting for the file lock")
+}
+
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+fn is_contended(e: &Error) -> bool {
+    e.raw_os_error() == crate::lock_contended_error().raw_os_error()
+}
+
+/// Returns how long to sleep before the next attempt, or `None` once the
+/// deadline has passed.
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+fn next_delay(
+    deadline: Option<Instant>,
+    policy: &mut dyn RetryPolicy,
+    attempt: u32,
+) -> Option<Duration> {
+    let Some(deadline) = deadline else {
+        return Some(policy.delay(attempt));
+    };
+    let remaining = deadline.checked_duration_since(Instant::now())?;
+    if remaining.is_zero() {
+        return None;
+    }
+    Some(policy.delay(attempt).min(remaining))
+}
+
+cfg_sync! {
+    /// Polls `try_lock` until it succeeds, fails with an error other than
+    /// contention, or `deadline` passes. Without a deadline, polls indefinitely.
+    pub(crate) fn lock_until<F>(deadline: Option<Instant>, policy: &mut dyn RetryPolicy, mut try_lock: F) -> Result<()>
+    where
+        F: FnMut() -> Result<()>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match try_lock() {
+                Err(e) if is_contended(&e) => match next_delay(deadline, policy, attempt) {
+                    Some(delay) => std::thread::sleep(delay),
+                    None => return Err(timed_out_error()),
+                },
+                res => return res,
+            }
+        }
+    }
+}
+
+cfg_sync! {
+    /// Polls `try_lock` until it succeeds, fails with an error other than
+    /// contention, or `token` is cancelled.
+    pub(crate) fn lock_cancellable<F>(
+        token: &crate::CancelToken,
+        policy: &mut dyn RetryPolicy,
+        mut try_lock: F,
+    ) -> Result<()>
+    where
+        F: FnMut() -> Result<()>,
+    {
+        let mut attempt = 0;
+        loop {
+            if token.is_cancelled() {
+                return Err(crate::lock_error::cancelled_error());
+            }
+            attempt += 1;
+            match try_lock() {
+                Err(e) if is_contended(&e) => {
+                    token.sleep(policy.delay(attempt));
+                }
+                res => return res,
+            }
+        }
+    }
+}
+
+cfg_async! {
+    /// Polls `try_lock` until it succeeds, fails with an error other than
+    /// contention, or `deadline` passes. Without a deadline, polls indefinitely.
+    pub(crate) async fn lock_until_async<F, S, Fut>(
+        deadline: Option<Instant>,
+        policy: &mut (dyn RetryPolicy + Send),
+        mut try_lock: F,
+        sleep: S,
+    ) -> Result<()>
+    where
+        F: FnMut() -> Result<()>,
+        S: Fn(Duration) -> Fut,
+        Fut: std::future::Future<Output = ()>,
+    {
+        let mut attempt = 0;
+        loop {
+            attempt += 1;
+            match try_lock() {
+                Err(e) if is_contended(&e) => match next_delay(deadline, policy, attempt) {
+                    Some(delay) => sleep(delay).await,
+                    None => return Err(timed_out_error()),
+                },
+                res => return res,
+            }
+        }
+    }
+}
+
+cfg_async_std! {
+    pub(crate) async fn sleep_async_std(delay: Duration) {
+        async_std::task::sleep(delay).await
+    }
+}
+
+cfg_smol! {
+    pub(crate) async fn sleep_smol(delay: Duration) {
+        smol::Timer::after(delay).await;
+    }
+}
+
+cfg_tokio! {
+    pub(crate) async fn sleep_tokio(delay: Duration) {
+        tokio::time::sleep(delay).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Tests that backoff delays double up to the maximum and stay within the
+    /// jitter bounds.
+    #[test]
+    fn exponential_backoff() {
+        let mut policy =
+            ExponentialBackoff::new(Duration::from_millis(4), Duration::from_millis(20));
+        for (attempt, bound) in [(1, 4), (2, 8), (3, 16), (4, 20), (40, 20)] {
+            let delay = policy.delay(attempt);
+            let bound = Duration::from_millis(bound);
+            assert!(
+                delay >= bound / 2 && delay <= bound,
+                "{:?} not within {:?}",
+                delay,
+                bound
+            );
+        }
+        assert_eq!(
+            FixedInterval::new(Duration::from_millis(3)).delay(7),
+            Duration::from_millis(3)
+        );
+    }
+}
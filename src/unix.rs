@@ -1,38 +1,174 @@
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
 macro_rules! lock_impl {
     ($file: ty) => {
         #[cfg(not(target_os = "wasi"))]
         pub fn lock_shared(file: &$file) -> std::io::Result<()> {
-            flock(file, rustix::fs::FlockOperation::LockShared)
+            lock(file, Some(crate::LockMode::Shared), true)
         }
 
         #[cfg(not(target_os = "wasi"))]
         pub fn lock_exclusive(file: &$file) -> std::io::Result<()> {
-            flock(file, rustix::fs::FlockOperation::LockExclusive)
+            lock(file, Some(crate::LockMode::Exclusive), true)
         }
 
+        #[cfg(not(target_os = "wasi"))]
+        pub fn lock_range(
+            file: &$file,
+            offset: u64,
+            len: u64,
+            mode: crate::LockMode,
+        ) -> std::io::Result<()> {
+            lock_range_of(file, Some(mode), true, offset, len)
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        pub fn upgrade(file: &$file) -> std::io::Result<crate::LockConversion> {
+            convert(file, crate::LockMode::Exclusive, true)
+        }
+
+        pub fn fs_stats(file: &$file) -> std::io::Result<crate::FsStats> {
+            crate::unix::fstatvfs(file.as_raw_fd())
+        }
+
+        lock_impl!(@try $file);
+    };
+    ($file: ty, $spawn_blocking: path, $sleep: path) => {
+        #[cfg(not(target_os = "wasi"))]
+        pub async fn lock_shared(file: &$file) -> std::io::Result<()> {
+            crate::unix::lock_async(
+                file.as_raw_fd(),
+                crate::lock_backend::use_default(),
+                crate::LockMode::Shared,
+                $sleep,
+            )
+            .await
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        pub async fn lock_exclusive(file: &$file) -> std::io::Result<()> {
+            crate::unix::lock_async(
+                file.as_raw_fd(),
+                crate::lock_backend::use_default(),
+                crate::LockMode::Exclusive,
+                $sleep,
+            )
+            .await
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        pub async fn upgrade(file: &$file) -> std::io::Result<crate::LockConversion> {
+            crate::unix::upgrade_async(file.as_raw_fd(), crate::lock_backend::use_default(), $sleep).await
+        }
+
+        pub async fn fs_stats(file: &$file) -> std::io::Result<crate::FsStats> {
+            // `fstatvfs` may block, so it runs on a duplicate of the descriptor.
+            let fd = unsafe { rustix::fd::BorrowedFd::borrow_raw(file.as_raw_fd()) }.try_clone_to_owned()?;
+            $spawn_blocking(move || crate::unix::fstatvfs(fd.as_raw_fd())).await
+        }
+
+        lock_impl!(@try $file);
+    };
+    (@try $file: ty) => {
         #[cfg(not(target_os = "wasi"))]
         pub fn try_lock_shared(file: &$file) -> std::io::Result<()> {
-            flock(file, rustix::fs::FlockOperation::NonBlockingLockShared)
+            lock(file, Some(crate::LockMode::Shared), false)
         }
 
         #[cfg(not(target_os = "wasi"))]
         pub fn try_lock_exclusive(file: &$file) -> std::io::Result<()> {
-            flock(file, rustix::fs::FlockOperation::NonBlockingLockExclusive)
+            lock(file, Some(crate::LockMode::Exclusive), false)
         }
 
         #[cfg(not(target_os = "wasi"))]
         pub fn unlock(file: &$file) -> std::io::Result<()> {
-            flock(file, rustix::fs::FlockOperation::Unlock)
+            lock(file, None, false)
         }
 
         #[cfg(not(target_os = "wasi"))]
-        fn flock(file: &$file, flag: rustix::fs::FlockOperation) -> std::io::Result<()> {
-            let borrowed_fd = unsafe { rustix::fd::BorrowedFd::borrow_raw(file.as_raw_fd()) };
+        pub fn try_lock_range(
+            file: &$file,
+            offset: u64,
+            len: u64,
+            mode: crate::LockMode,
+        ) -> std::io::Result<()> {
+            lock_range_of(file, Some(mode), false, offset, len)
+        }
 
-            match rustix::fs::flock(borrowed_fd, flag) {
-                Ok(_) => Ok(()),
-                Err(e) => Err(std::io::Error::from_raw_os_error(e.raw_os_error())),
-            }
+        #[cfg(not(target_os = "wasi"))]
+        pub fn unlock_range(file: &$file, offset: u64, len: u64) -> std::io::Result<()> {
+            lock_range_of(file, None, false, offset, len)
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        pub fn try_upgrade(file: &$file) -> std::io::Result<crate::LockConversion> {
+            convert(file, crate::LockMode::Exclusive, false)
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        pub fn downgrade(file: &$file) -> std::io::Result<crate::LockConversion> {
+            convert(file, crate::LockMode::Shared, false)
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        pub fn lock_holder(
+            file: &$file,
+            mode: crate::LockMode,
+            offset: u64,
+            len: u64,
+        ) -> std::io::Result<Option<crate::LockHolder>> {
+            crate::unix::lock_holder(
+                file.as_raw_fd(),
+                crate::lock_backend::use_default(),
+                mode,
+                offset,
+                len,
+            )
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        pub fn lock_status(file: &$file) -> std::io::Result<crate::LockStatus> {
+            crate::unix::lock_status(file.as_raw_fd(), crate::lock_backend::use_default())
+        }
+
+        pub fn file_id(file: &$file) -> std::io::Result<(u64, u64)> {
+            crate::unix::file_id(file.as_raw_fd())
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        fn lock(file: &$file, mode: Option<crate::LockMode>, wait: bool) -> std::io::Result<()> {
+            crate::unix::lock(file.as_raw_fd(), crate::lock_backend::use_default(), mode, wait)
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        fn convert(
+            file: &$file,
+            mode: crate::LockMode,
+            wait: bool,
+        ) -> std::io::Result<crate::LockConversion> {
+            crate::unix::convert(file.as_raw_fd(), crate::lock_backend::use_default(), mode, wait)
+        }
+
+        #[cfg(not(target_os = "wasi"))]
+        fn lock_range_of(
+            file: &$file,
+            mode: Option<crate::LockMode>,
+            wait: bool,
+            offset: u64,
+            len: u64,
+        ) -> std::io::Result<()> {
+            crate::unix::lock_range(
+                file.as_raw_fd(),
+                crate::lock_backend::use_default(),
+                mode,
+                wait,
+                offset,
+                len,
+            )
         }
     };
 }
@@ -42,8 +178,22 @@ pub(crate) mod async_impl;
 #[cfg(feature = "sync")]
 pub(crate) mod sync_impl;
 
-use crate::FsStats;
+use crate::{FsStats, FsType};
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+use crate::{LockBackend, LockConversion, LockHolder, LockMode, LockStatus};
 
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+use std::io::ErrorKind;
 use std::io::{Error, Result};
 use std::path::Path;
 
@@ -51,14 +201,710 @@ pub fn lock_error() -> Error {
     Error::from_raw_os_error(rustix::io::Errno::WOULDBLOCK.raw_os_error())
 }
 
+pub(crate) fn deadlock_error() -> Error {
+    Error::from_raw_os_error(rustix::io::Errno::DEADLK.raw_os_error())
+}
+
+/// Applies `flag` to the file, retrying if a signal interrupts a blocking lock.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    not(target_os = "wasi")
+))]
+pub(crate) fn flock(
+    fd: rustix::fd::BorrowedFd<'_>,
+    flag: rustix::fs::FlockOperation,
+) -> Result<()> {
+    loop {
+        match rustix::fs::flock(fd, flag) {
+            Ok(_) => return Ok(()),
+            Err(rustix::io::Errno::INTR) => continue,
+            Err(e) => return Err(Error::from_raw_os_error(e.raw_os_error())),
+        }
+    }
+}
+
+/// Returns an ID which is the same for every open file referring to the same
+/// file on disk.
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+pub(crate) fn file_id(fd: std::os::unix::io::RawFd) -> Result<(u64, u64)> {
+    let stat = rustix::fs::fstat(unsafe { rustix::fd::BorrowedFd::borrow_raw(fd) })?;
+    Ok((stat.st_dev as u64, stat.st_ino as u64))
+}
+
+/// Locks the whole file with `backend`, or unlocks it if `mode` is `None`,
+/// waiting for conflicting locks if `wait` is set.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    not(target_os = "wasi")
+))]
+pub(crate) fn lock(
+    fd: std::os::unix::io::RawFd,
+    backend: LockBackend,
+    mode: Option<LockMode>,
+    wait: bool,
+) -> Result<()> {
+    use rustix::fs::FlockOperation;
+
+    match backend {
+        LockBackend::Flock => {
+            let flag = match (mode, wait) {
+                (Some(LockMode::Shared), true) => FlockOperation::LockShared,
+                (Some(LockMode::Exclusive), true) => FlockOperation::LockExclusive,
+                (Some(LockMode::Shared), false) => FlockOperation::NonBlockingLockShared,
+                (Some(LockMode::Exclusive), false) => FlockOperation::NonBlockingLockExclusive,
+                (None, _) => FlockOperation::Unlock,
+            };
+            flock(unsafe { rustix::fd::BorrowedFd::borrow_raw(fd) }, flag)
+        }
+        backend => lock_range(fd, backend, mode, wait, 0, 0),
+    }
+}
+
+/// Locks `len` bytes starting at `offset` with `backend`, or unlocks them if
+/// `mode` is `None`, waiting for conflicting locks if `wait` is set. `flock`
+/// cannot lock ranges, so the `Flock` backend uses POSIX record locks instead.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    not(target_os = "wasi")
+))]
+pub(crate) fn lock_range(
+    fd: std::os::unix::io::RawFd,
+    backend: LockBackend,
+    mode: Option<LockMode>,
+    wait: bool,
+    offset: u64,
+    len: u64,
+) -> Result<()> {
+    let cmd = match (backend, wait) {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        (LockBackend::Ofd, true) => libc::F_OFD_SETLKW,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        (LockBackend::Ofd, false) => libc::F_OFD_SETLK,
+        (_, true) => libc::F_SETLKW,
+        (_, false) => libc::F_SETLK,
+    };
+    fcntl_lock(fd, cmd, mode, offset, len)
+}
+
+/// Applies a POSIX record lock to `len` bytes starting at `offset` (to the end of
+/// the file if `len` is zero), or removes it if `mode` is `None`. A blocking
+/// lock interrupted by a signal is retried.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    not(target_os = "wasi")
+))]
+pub(crate) fn fcntl_lock(
+    fd: std::os::unix::io::RawFd,
+    cmd: libc::c_int,
+    mode: Option<LockMode>,
+    offset: u64,
+    len: u64,
+) -> Result<()> {
+    let (Ok(start), Ok(len)) = (libc::off_t::try_from(offset), libc::off_t::try_from(len)) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "lock range out of bounds",
+        ));
+    };
+
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = match mode {
+        Some(LockMode::Shared) => libc::F_RDLCK,
+        Some(LockMode::Exclusive) => libc::F_WRLCK,
+        None => libc::F_UNLCK,
+    } as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    lock.l_start = start;
+    lock.l_len = len;
+
+    let err = loop {
+        if unsafe { libc::fcntl(fd, cmd, &lock) } != -1 {
+            return Ok(());
+        }
+        let err = Error::last_os_error();
+        if err.kind() != ErrorKind::Interrupted {
+            break err;
+        }
+    };
+    // POSIX allows a contended `F_SETLK` to fail with either `EACCES` or `EAGAIN`.
+    match err.raw_os_error() {
+        Some(libc::EACCES) | Some(libc::EAGAIN) => Err(lock_error()),
+        _ => Err(err),
+    }
+}
+
+/// Returns the first lock that would conflict with locking `len` bytes starting
+/// at `offset` with `backend` in `mode`, without locking anything.
+///
+/// With the `Flock` backend, the POSIX record locks its range locks are taken
+/// with are queried first. `fcntl` cannot see `flock` locks, so on Linux they
+/// are then looked up in `/proc/locks`, and elsewhere the query fails with an
+/// error of kind `Unsupported`.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    not(target_os = "wasi")
+))]
+pub(crate) fn lock_holder(
+    fd: std::os::unix::io::RawFd,
+    backend: LockBackend,
+    mode: LockMode,
+    offset: u64,
+    len: u64,
+) -> Result<Option<LockHolder>> {
+    let (Ok(start), Ok(len)) = (libc::off_t::try_from(offset), libc::off_t::try_from(len)) else {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "lock range out of bounds",
+        ));
+    };
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    if backend == LockBackend::Flock {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "flock locks cannot be queried on this platform",
+        ));
+    }
+    let cmd = match backend {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        LockBackend::Ofd => libc::F_OFD_GETLK,
+        _ => libc::F_GETLK,
+    };
+
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = match mode {
+        LockMode::Shared => libc::F_RDLCK,
+        LockMode::Exclusive => libc::F_WRLCK,
+    } as _;
+    lock.l_whence = libc::SEEK_SET as _;
+    lock.l_start = start;
+    lock.l_len = len;
+
+    if unsafe { libc::fcntl(fd, cmd, &mut lock) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    let holder_mode = match lock.l_type as libc::c_int {
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        libc::F_UNLCK if backend == LockBackend::Flock => return flock_holder(fd, mode),
+        libc::F_UNLCK => return Ok(None),
+        libc::F_RDLCK => LockMode::Shared,
+        _ => LockMode::Exclusive,
+    };
+    Ok(Some(LockHolder {
+        mode: holder_mode,
+        offset: lock.l_start as u64,
+        len: lock.l_len as u64,
+        // OFD locks are reported with a PID of -1.
+        pid: u32::try_from(lock.l_pid).ok(),
+    }))
+}
+
+/// Returns the first `flock` lock on the file open as `fd` which conflicts with
+/// `mode`, other than the one held through `fd`, as listed in `/proc/locks`.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    any(target_os = "linux", target_os = "android")
+))]
+fn flock_holder(fd: std::os::unix::io::RawFd, mode: LockMode) -> Result<Option<LockHolder>> {
+    use crate::locks::{self, LockType};
+
+    let fd_locks = locks::fd_locks(fd)?;
+    let (device, inode) = listed_file_id(fd, &fd_locks)?;
+    let key = |lock: &locks::SystemLock| (lock.mode(), lock.pid());
+    // flock conflicts are between open file descriptions, so other `File`s of
+    // this process may hold conflicting locks, and only the one held through
+    // `fd` itself is skipped.
+    let mut own = fd_locks
+        .into_iter()
+        .find(|lock| lock.kind() == LockType::Flock && !lock.is_blocked());
+    Ok(locks::system_locks()?
+        .into_iter()
+        .filter(|lock| {
+            !lock.is_blocked()
+                && lock.kind() == LockType::Flock
+                && (lock.device(), lock.inode()) == (device, inode)
+        })
+        .find(|lock| {
+            if own.as_ref().is_some_and(|own| key(own) == key(lock)) {
+                own = None;
+                return false;
+            }
+            mode == LockMode::Exclusive || lock.mode() == LockMode::Exclusive
+        })
+        .map(|lock| LockHolder {
+            mode: lock.mode(),
+            offset: 0,
+            len: 0,
+            pid: lock.pid(),
+        }))
+}
+
+/// Returns the device and inode under which `/proc/locks` lists the locks on
+/// the file open as `fd`, given `fd_locks`, the locks listed for `fd` itself.
+///
+/// `/proc/locks` lists the device of the file system, which is not the `st_dev`
+/// of files on btrfs subvolumes, or on other file systems which report an
+/// anonymous device. A lock held through `fd` gives the listed device; without
+/// one, `st_dev` is used, and the locks on such a file are not found.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    any(target_os = "linux", target_os = "android")
+))]
+fn listed_file_id(
+    fd: std::os::unix::io::RawFd,
+    fd_locks: &[crate::locks::SystemLock],
+) -> Result<(u64, u64)> {
+    let (device, inode) = file_id(fd)?;
+    Ok((fd_locks.first().map_or(device, |lock| lock.device()), inode))
+}
+
+/// Takes a lease of `mode` on the file open as `fd`, or releases the lease if
+/// `mode` is `None`.
+#[cfg(all(feature = "sync", any(target_os = "linux", target_os = "android")))]
+pub(crate) fn set_lease(fd: std::os::unix::io::RawFd, mode: Option<LockMode>) -> Result<()> {
+    let lease = match mode {
+        Some(LockMode::Shared) => libc::F_RDLCK,
+        Some(LockMode::Exclusive) => libc::F_WRLCK,
+        None => libc::F_UNLCK,
+    };
+    if mode.is_some() {
+        crate::lease::watch(fd)?;
+    }
+    if unsafe { libc::fcntl(fd, libc::F_SETLEASE, lease) } == -1 {
+        return Err(Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Returns the lease held on the file open as `fd`.
+#[cfg(all(feature = "sync", any(target_os = "linux", target_os = "android")))]
+pub(crate) fn lease(fd: std::os::unix::io::RawFd) -> Result<Option<LockMode>> {
+    match unsafe { libc::fcntl(fd, libc::F_GETLEASE) } {
+        -1 => Err(Error::last_os_error()),
+        libc::F_RDLCK => Ok(Some(LockMode::Shared)),
+        libc::F_WRLCK => Ok(Some(LockMode::Exclusive)),
+        _ => Ok(None),
+    }
+}
+
+/// Returns whether the file open as `fd` is locked with `backend`, without
+/// taking a lock.
+///
+/// On Linux this reads `/proc/locks`, which lists the locks of every process,
+/// the calling one included. OFD locks are listed there without a process, so
+/// they are held by the calling process if one of its descriptors lists them in
+/// `/proc/self/fdinfo`. On btrfs subvolumes, locks are only found while `fd`
+/// holds one itself (see `listed_file_id`). Elsewhere, POSIX record locks are
+/// queried with `F_GETLK`, which does not see the locks of the calling process,
+/// and `flock` locks cannot be queried at all.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    not(target_os = "wasi")
+))]
+pub(crate) fn lock_status(
+    fd: std::os::unix::io::RawFd,
+    backend: LockBackend,
+) -> Result<LockStatus> {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    {
+        use crate::locks::{self, LockType};
+
+        let kind = match backend {
+            LockBackend::Flock => LockType::Flock,
+            LockBackend::Posix => LockType::Posix,
+            LockBackend::Ofd => LockType::Ofd,
+        };
+        let (device, inode) = listed_file_id(fd, &locks::fd_locks(fd)?)?;
+        let pid = std::process::id();
+        let own_ofd = match backend {
+            LockBackend::Ofd => locks::process_locks()?,
+            _ => Vec::new(),
+        };
+        let mut status = LockStatus::Unlocked;
+        for lock in locks::system_locks()? {
+            if lock.is_blocked()
+                || lock.kind() != kind
+                || (lock.device(), lock.inode()) != (device, inode)
+            {
+                continue;
+            }
+            let held_by_self = match lock.pid() {
+                Some(holder) => holder == pid,
+                None => own_ofd.iter().any(|own| {
+                    !own.is_blocked()
+                        && own.kind() == kind
+                        && (own.device(), own.inode()) == (device, inode)
+                        && (own.mode(), own.start(), own.end())
+                            == (lock.mode(), lock.start(), lock.end())
+                }),
+            };
+            status = match (status, lock.mode()) {
+                (LockStatus::ExclusiveHeld { .. }, _) => status,
+                (_, LockMode::Exclusive) => LockStatus::ExclusiveHeld { held_by_self },
+                (
+                    LockStatus::SharedHeld {
+                        held_by_self: others,
+                    },
+                    LockMode::Shared,
+                ) => LockStatus::SharedHeld {
+                    held_by_self: held_by_self || others,
+                },
+                (LockStatus::Unlocked, LockMode::Shared) => LockStatus::SharedHeld { held_by_self },
+            };
+        }
+        Ok(status)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    {
+        if backend == LockBackend::Flock {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                "flock locks cannot be queried on this platform",
+            ));
+        }
+        // Only an exclusive lock conflicts with a shared one.
+        if lock_holder(fd, backend, LockMode::Shared, 0, 0)?.is_some() {
+            return Ok(LockStatus::ExclusiveHeld {
+                held_by_self: false,
+            });
+        }
+        match lock_holder(fd, backend, LockMode::Exclusive, 0, 0)? {
+            Some(_) => Ok(LockStatus::SharedHeld {
+                held_by_self: false,
+            }),
+            None => Ok(LockStatus::Unlocked),
+        }
+    }
+}
+
+/// Converts the whole-file lock held through `fd` with `backend` to `mode`,
+/// waiting for conflicting locks if `wait` is set.
+///
+/// POSIX and OFD locks are converted atomically, and keep their original mode
+/// if the conversion fails. `flock` removes the original lock before taking the
+/// new one, so on failure it is taken again without waiting.
+#[cfg(all(
+    any(
+        feature = "sync",
+        feature = "smol",
+        feature = "async-std",
+        feature = "tokio"
+    ),
+    not(target_os = "wasi")
+))]
+pub(crate) fn convert(
+    fd: std::os::unix::io::RawFd,
+    backend: LockBackend,
+    mode: LockMode,
+    wait: bool,
+) -> Result<LockConversion> {
+    let original = match mode {
+        LockMode::Shared => LockMode::Exclusive,
+        LockMode::Exclusive => LockMode::Shared,
+    };
+    match backend {
+        LockBackend::Flock => match lock(fd, backend, Some(mode), wait) {
+            Ok(()) => Ok(LockConversion::Reacquired),
+            Err(e) => Err(crate::lock_conversion::restore(
+                lock(fd, backend, Some(original), false),
+                e,
+            )),
+        },
+        backend => lock(fd, backend, Some(mode), wait).map(|_| LockConversion::Atomic),
+    }
+}
+
+/// Locks the whole file with `backend` without blocking the executor of an
+/// async runtime, polling with an [`ExponentialBackoff`](crate::ExponentialBackoff)
+/// policy while the file is contended.
+///
+/// A blocking lock waited for on another thread could not be called off if the
+/// returned future is dropped, and would be granted to the open file later on,
+/// so no thread is kept waiting. Nothing is locked between polls, so dropping
+/// the future leaves the file as it was.
+#[cfg(all(
+    any(feature = "smol", feature = "async-std", feature = "tokio"),
+    not(target_os = "wasi")
+))]
+pub(crate) async fn lock_async<Z, Fut>(
+    fd: std::os::unix::io::RawFd,
+    backend: LockBackend,
+    mode: LockMode,
+    sleep: Z,
+) -> Result<()>
+where
+    Z: Fn(std::time::Duration) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let mut policy = crate::ExponentialBackoff::default();
+    crate::retry::lock_until_async(
+        None,
+        &mut policy,
+        || lock(fd, backend, Some(mode), false),
+        sleep,
+    )
+    .await
+}
+
+/// Converts a shared whole-file lock to an exclusive one like `convert`,
+/// without blocking the executor of an async runtime. Like `lock_async`, the
+/// conversion is polled. Every failed attempt leaves the shared lock held, so if
+/// the returned future is dropped, the file is left locked shared. With `flock`
+/// each attempt is a non-atomic `convert`, which briefly releases the shared
+/// lock, and fails with a lock lost error if it cannot be taken back.
+#[cfg(all(
+    any(feature = "smol", feature = "async-std", feature = "tokio"),
+    not(target_os = "wasi")
+))]
+pub(crate) async fn upgrade_async<Z, Fut>(
+    fd: std::os::unix::io::RawFd,
+    backend: LockBackend,
+    sleep: Z,
+) -> Result<LockConversion>
+where
+    Z: Fn(std::time::Duration) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let mut policy = crate::ExponentialBackoff::default();
+    let mut conversion = LockConversion::Atomic;
+    crate::retry::lock_until_async(
+        None,
+        &mut policy,
+        || convert(fd, backend, LockMode::Exclusive, false).map(|c| conversion = c),
+        sleep,
+    )
+    .await?;
+    Ok(conversion)
+}
+
+/// Returns whether a process with ID `pid` exists, whether or not it may be
+/// signalled by the calling process.
+#[cfg(feature = "sync")]
+pub(crate) fn process_exists(pid: u32) -> Result<bool> {
+    match libc::pid_t::try_from(pid) {
+        Ok(pid) if pid > 0 => {}
+        _ => return Ok(false),
+    }
+    if unsafe { libc::kill(pid as libc::pid_t, 0) } == 0 {
+        return Ok(true);
+    }
+    let err = Error::last_os_error();
+    match err.raw_os_error() {
+        Some(libc::EPERM) => Ok(true),
+        Some(libc::ESRCH) => Ok(false),
+        _ => Err(err),
+    }
+}
+
+/// Returns the stats of the file system containing `path`. On Linux and
+/// Android they are built from a single `statfs` call, which also gives the
+/// type of the file system.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn statvfs(path: impl AsRef<Path>) -> Result<FsStats> {
+    let stat = rustix::fs::statfs(path.as_ref())?;
+    Ok(fs_stats(statfs_to_statvfs(stat), fs_type(stat)))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
 pub fn statvfs(path: impl AsRef<Path>) -> Result<FsStats> {
     match rustix::fs::statvfs(path.as_ref()) {
-        Ok(stat) => Ok(FsStats {
-            free_space: stat.f_frsize * stat.f_bfree,
-            available_space: stat.f_frsize * stat.f_bavail,
-            total_space: stat.f_frsize * stat.f_blocks,
-            allocation_granularity: stat.f_frsize,
-        }),
+        Ok(stat) => Ok(fs_stats(
+            stat,
+            filesystem_type(path.as_ref()).unwrap_or(FsType::Other),
+        )),
+        Err(e) => Err(std::io::Error::from_raw_os_error(e.raw_os_error())),
+    }
+}
+
+/// Returns the stats of the file system holding the file open as `fd`.
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+pub(crate) fn fstatvfs(fd: std::os::unix::io::RawFd) -> Result<FsStats> {
+    let fd = unsafe { rustix::fd::BorrowedFd::borrow_raw(fd) };
+    match rustix::fs::fstatvfs(fd) {
+        Ok(stat) => Ok(fs_stats(stat, fd_fs_type(fd))),
         Err(e) => Err(std::io::Error::from_raw_os_error(e.raw_os_error())),
     }
 }
+
+/// Converts the result of `statfs` to that of `statvfs`, as glibc does.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+#[allow(clippy::unnecessary_cast)] // The field types differ between targets.
+fn statfs_to_statvfs(stat: rustix::fs::StatFs) -> rustix::fs::StatVfs {
+    // SAFETY: `f_fsid` is two `int`s on every Linux target, whether it is
+    // the kernel's or the C library's type, whose fields are private.
+    let [low, high]: [i32; 2] = unsafe { std::mem::transmute(stat.f_fsid) };
+    rustix::fs::StatVfs {
+        f_bsize: stat.f_bsize as u64,
+        f_frsize: if stat.f_frsize != 0 {
+            stat.f_frsize as u64
+        } else {
+            stat.f_bsize as u64
+        },
+        f_blocks: stat.f_blocks as u64,
+        f_bfree: stat.f_bfree as u64,
+        f_bavail: stat.f_bavail as u64,
+        f_files: stat.f_files as u64,
+        f_ffree: stat.f_ffree as u64,
+        f_favail: stat.f_ffree as u64,
+        f_fsid: u64::from(low as u32) | u64::from(high as u32) << 32,
+        f_flag: rustix::fs::StatVfsMountFlags::from_bits_retain(stat.f_flags as u64),
+        f_namemax: stat.f_namelen as u64,
+    }
+}
+
+fn fs_stats(stat: rustix::fs::StatVfs, fs_type: FsType) -> FsStats {
+    // File systems which allocate inodes dynamically, such as btrfs, report
+    // no inodes at all rather than an unlimited number.
+    let inodes = |count| (stat.f_files != 0).then_some(count);
+    FsStats {
+        free_space: stat.f_frsize * stat.f_bfree,
+        available_space: stat.f_frsize * stat.f_bavail,
+        total_space: stat.f_frsize * stat.f_blocks,
+        allocation_granularity: stat.f_frsize,
+        fs_type,
+        total_inodes: inodes(stat.f_files),
+        free_inodes: inodes(stat.f_ffree),
+        available_inodes: inodes(stat.f_favail),
+        max_name_len: stat.f_namemax,
+        fsid: stat.f_fsid,
+        block_size: stat.f_bsize,
+        read_only: stat.f_flag.contains(rustix::fs::StatVfsMountFlags::RDONLY),
+        nosuid: stat.f_flag.contains(rustix::fs::StatVfsMountFlags::NOSUID),
+    }
+}
+
+/// Returns the type of the file system containing `path`, from the magic
+/// number `statfs` returns on Linux and the type name it returns on the BSDs
+/// and macOS. Other systems, some of which have no `statfs`, report
+/// `FsType::Other`.
+pub fn filesystem_type(path: &Path) -> Result<FsType> {
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    {
+        Ok(fs_type(rustix::fs::statfs(path)?))
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    )))]
+    {
+        rustix::fs::statvfs(path)?;
+        Ok(FsType::Other)
+    }
+}
+
+/// Returns the type of the file system holding the file open as `fd`, or
+/// `FsType::Other` if it can't be determined (see `filesystem_type`).
+#[cfg(any(
+    feature = "sync",
+    feature = "smol",
+    feature = "async-std",
+    feature = "tokio"
+))]
+fn fd_fs_type(fd: rustix::fd::BorrowedFd<'_>) -> FsType {
+    #[cfg(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    ))]
+    {
+        rustix::fs::fstatfs(fd).map_or(FsType::Other, fs_type)
+    }
+    #[cfg(not(any(
+        target_os = "linux",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "ios",
+        target_os = "freebsd",
+        target_os = "openbsd",
+        target_os = "dragonfly"
+    )))]
+    {
+        let _ = fd;
+        FsType::Other
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn fs_type(stat: rustix::fs::StatFs) -> FsType {
+    // Magic numbers are 32 bits, but `f_type` is signed on some targets.
+    FsType::from_magic(stat.f_type as u32)
+}
+
+#[cfg(any(
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "dragonfly"
+))]
+fn fs_type(stat: rustix::fs::StatFs) -> FsType {
+    let name = unsafe { std::ffi::CStr::from_ptr(stat.f_fstypename.as_ptr()) };
+    FsType::from_name(&name.to_string_lossy())
+}
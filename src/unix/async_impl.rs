@@ -5,7 +5,6 @@ macro_rules! allocate {
             target_os = "freebsd",
             target_os = "android",
             target_os = "emscripten",
-            target_os = "nacl",
             target_os = "macos",
             target_os = "ios",
             target_os = "watchos",
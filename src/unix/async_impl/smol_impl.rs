@@ -2,7 +2,7 @@ use std::os::unix::fs::MetadataExt;
 use std::os::unix::io::AsRawFd;
 use smol::fs::File;
 
-lock_impl!(File);
+lock_impl!(File, crate::blocking::spawn_smol, crate::retry::sleep_smol);
 allocate!(File);
 allocate_size!(File);
 
@@ -12,7 +12,7 @@ mod test {
 
     use smol::fs;
 
-    use crate::{lock_contended_error, smol::AsyncFileExt};
+    use crate::{lock_contended_error, smol::AsyncFileExt, LockBackend, LockMode};
 
     /// Tests that locking a file descriptor will replace any existing locks
     /// held on the file descriptor.
@@ -24,14 +24,53 @@ mod test {
         let file2 = fs::OpenOptions::new().write(true).create(true).open(&path).await.unwrap();
 
         // Creating a shared lock will drop an exclusive lock.
-        file1.lock_exclusive().unwrap();
-        file1.lock_shared().unwrap();
-        file2.lock_shared().unwrap();
+        file1.lock_exclusive().await.unwrap();
+        file1.lock_shared().await.unwrap();
+        file2.lock_shared().await.unwrap();
 
         // Attempting to replace a shared lock with an exclusive lock will fail
         // with multiple lock holders, and remove the original shared lock.
         assert_eq!(file2.try_lock_exclusive().unwrap_err().raw_os_error(),
                    lock_contended_error().raw_os_error());
-        file1.lock_shared().unwrap();
+        file1.lock_shared().await.unwrap();
     }
-}
\ No newline at end of file
+
+    /// Tests that range locks can be taken, converted and released.
+    #[smol_potat::test]
+    async fn lock_range() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+
+        file.lock_range(0, 10, LockMode::Exclusive).await.unwrap();
+        file.try_lock_range(5, 10, LockMode::Shared).unwrap();
+        file.unlock_range(0, 0).unwrap();
+        assert_eq!(file.try_lock_range(u64::MAX, 1, LockMode::Shared).unwrap_err().kind(),
+                   std::io::ErrorKind::InvalidInput);
+    }
+
+    /// Tests that OFD locks taken through `locks` conflict between two open
+    /// files of the same process.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[smol_potat::test]
+    async fn lock_backend() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
+        let file2 = fs::OpenOptions::new().read(true).write(true).open(&path).await.unwrap();
+        let locks1 = file1.locks(LockBackend::Ofd);
+        let locks2 = file2.locks(LockBackend::Ofd);
+
+        locks1.lock_exclusive().await.unwrap();
+        assert_eq!(locks2.try_lock_shared().unwrap_err().raw_os_error(),
+                   lock_contended_error().raw_os_error());
+        assert!(locks2.lock_holder(LockMode::Shared, 0, 0).unwrap().is_some());
+        locks1.downgrade().unwrap();
+        locks2.lock_shared().await.unwrap();
+        locks1.unlock().unwrap();
+        locks2.upgrade().await.unwrap();
+        locks2.unlock().unwrap();
+        locks1.lock_range(0, 10, LockMode::Exclusive).await.unwrap();
+        locks2.try_lock_range(10, 10, LockMode::Exclusive).unwrap();
+    }
+}
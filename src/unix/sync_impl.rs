@@ -4,6 +4,62 @@ use std::os::unix::io::AsRawFd;
 
 lock_impl!(File);
 
+pub fn lock_with(
+    file: &File,
+    backend: crate::LockBackend,
+    mode: Option<crate::LockMode>,
+    wait: bool,
+) -> std::io::Result<()> {
+    crate::unix::lock(file.as_raw_fd(), backend, mode, wait)
+}
+
+pub fn lock_range_with(
+    file: &File,
+    backend: crate::LockBackend,
+    mode: Option<crate::LockMode>,
+    wait: bool,
+    offset: u64,
+    len: u64,
+) -> std::io::Result<()> {
+    crate::unix::lock_range(file.as_raw_fd(), backend, mode, wait, offset, len)
+}
+
+pub fn convert_with(
+    file: &File,
+    backend: crate::LockBackend,
+    mode: crate::LockMode,
+    wait: bool,
+) -> std::io::Result<crate::LockConversion> {
+    crate::unix::convert(file.as_raw_fd(), backend, mode, wait)
+}
+
+pub fn lock_holder_with(
+    file: &File,
+    backend: crate::LockBackend,
+    mode: crate::LockMode,
+    offset: u64,
+    len: u64,
+) -> std::io::Result<Option<crate::LockHolder>> {
+    crate::unix::lock_holder(file.as_raw_fd(), backend, mode, offset, len)
+}
+
+pub fn lock_status_with(
+    file: &File,
+    backend: crate::LockBackend,
+) -> std::io::Result<crate::LockStatus> {
+    crate::unix::lock_status(file.as_raw_fd(), backend)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn set_lease(file: &File, mode: Option<crate::LockMode>) -> std::io::Result<()> {
+    crate::unix::set_lease(file.as_raw_fd(), mode)
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn lease(file: &File) -> std::io::Result<Option<crate::LockMode>> {
+    crate::unix::lease(file.as_raw_fd())
+}
+
 pub fn allocated_size(file: &File) -> std::io::Result<u64> {
     file.metadata().map(|m| m.blocks() * 512)
 }
@@ -13,7 +69,6 @@ pub fn allocated_size(file: &File) -> std::io::Result<u64> {
     target_os = "freebsd",
     target_os = "android",
     target_os = "emscripten",
-    target_os = "nacl",
     target_os = "macos",
     target_os = "ios",
     target_os = "watchos",
@@ -55,8 +110,125 @@ mod test {
     extern crate tempdir;
 
     use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+
+    use crate::{lock_contended_error, FileExt, LockBackend, LockConversion, LockMode, LockStatus};
 
-    use crate::{lock_contended_error, FileExt};
+    const CHILD_ENV: &str = "FS4_LOCK_CHILD";
+
+    /// Runs the lock operation named by `FS4_LOCK_CHILD` on the file at the
+    /// path it names, and prints the outcome. Run as a child process by the
+    /// other tests, since POSIX record locks never conflict within a single
+    /// process, and forking the multithreaded test harness is unsafe.
+    #[test]
+    #[ignore]
+    fn lock_child() {
+        let Ok(request) = std::env::var(CHILD_ENV) else {
+            return;
+        };
+        let (path, op) = request.split_once('\n').unwrap();
+        let args: Vec<&str> = op.split(' ').collect();
+        let mode = |arg: &str| match arg {
+            "shared" => LockMode::Shared,
+            _ => LockMode::Exclusive,
+        };
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .unwrap();
+        let locked = |result: std::io::Result<()>| match result {
+            Ok(()) => "locked".to_string(),
+            Err(e) if e.raw_os_error() == lock_contended_error().raw_os_error() => {
+                "contended".to_string()
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        let outcome = match args[..] {
+            ["try_lock_range", offset, len, m] => {
+                locked(file.try_lock_range(offset.parse().unwrap(), len.parse().unwrap(), mode(m)))
+            }
+            ["try_lock_posix", m] => {
+                let locks = file.locks(LockBackend::Posix);
+                locked(match mode(m) {
+                    LockMode::Shared => locks.try_lock_shared(),
+                    LockMode::Exclusive => locks.try_lock_exclusive(),
+                })
+            }
+            ["lock_holder", m, offset, len] => {
+                match file.lock_holder(mode(m), offset.parse().unwrap(), len.parse().unwrap()) {
+                    Ok(Some(holder)) => holder.to_string(),
+                    Ok(None) => "none".to_string(),
+                    Err(e) => format!("error: {}", e),
+                }
+            }
+            ["lock_status"] => match file.lock_status() {
+                Ok(status) => format!("{:?}", status),
+                Err(e) => format!("error: {}", e),
+            },
+            ["lock_status", "ofd"] => match file.locks(LockBackend::Ofd).lock_status() {
+                Ok(status) => format!("{:?}", status),
+                Err(e) => format!("error: {}", e),
+            },
+            ["lock_interrupted"] => {
+                let other = fs::OpenOptions::new().write(true).open(path).unwrap();
+                locked(interrupt_lock(file, other))
+            }
+            ["default_posix"] => {
+                // With POSIX locks, a second descriptor in the same process
+                // does not conflict, but a flock on it is unaffected. Once
+                // used, the default cannot be changed.
+                crate::set_default_lock_backend(LockBackend::Posix).unwrap();
+                let other = fs::OpenOptions::new().write(true).open(path).unwrap();
+                let ok = crate::default_lock_backend() == LockBackend::Posix
+                    && FileExt::lock_exclusive(&file).is_ok()
+                    && FileExt::try_lock_exclusive(&other).is_ok()
+                    && other.locks(LockBackend::Flock).try_lock_exclusive().is_ok()
+                    && crate::set_default_lock_backend(LockBackend::Flock).is_err()
+                    && FileExt::unlock(&file).is_ok();
+                ok.to_string()
+            }
+            _ => panic!("unknown lock operation {:?}", op),
+        };
+        println!("fs4-child: {}", outcome);
+    }
+
+    /// Runs `op` on the file at `path` in a child process (see `lock_child`),
+    /// and returns the outcome it printed.
+    fn in_child(path: &Path, op: &str) -> String {
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "unix::sync_impl::test::lock_child",
+                "--ignored",
+                "--nocapture",
+            ])
+            .env(CHILD_ENV, format!("{}\n{}", path.display(), op))
+            .output()
+            .unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        match stdout
+            .lines()
+            .find_map(|line| Some(line.split_once("fs4-child: ")?.1))
+        {
+            Some(outcome) => outcome.to_string(),
+            None => panic!(
+                "lock child failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            ),
+        }
+    }
+
+    /// Returns whether another process can lock the given range of the file at
+    /// `path`.
+    fn child_can_lock(path: &Path, offset: u64, len: u64, mode: LockMode) -> bool {
+        let mode = match mode {
+            LockMode::Shared => "shared",
+            LockMode::Exclusive => "exclusive",
+        };
+        in_child(path, &format!("try_lock_range {} {} {}", offset, len, mode)) == "locked"
+    }
 
     /// Tests that locking a file descriptor will replace any existing locks
     /// held on the file descriptor.
@@ -67,25 +239,552 @@ mod test {
         let file1 = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .unwrap();
         let file2 = fs::OpenOptions::new()
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .unwrap();
 
         // Creating a shared lock will drop an exclusive lock.
-        file1.lock_exclusive().unwrap();
-        file1.lock_shared().unwrap();
-        file2.lock_shared().unwrap();
+        FileExt::lock_exclusive(&file1).unwrap();
+        FileExt::lock_shared(&file1).unwrap();
+        FileExt::lock_shared(&file2).unwrap();
 
         // Attempting to replace a shared lock with an exclusive lock will fail
         // with multiple lock holders, and remove the original shared lock.
         assert_eq!(
-            file2.try_lock_exclusive().unwrap_err().raw_os_error(),
+            FileExt::try_lock_exclusive(&file2)
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        FileExt::lock_shared(&file1).unwrap();
+    }
+
+    /// Tests that overlapping range locks conflict across processes while
+    /// disjoint and shared ones do not.
+    #[test]
+    fn lock_range_overlap() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        file.lock_range(0, 10, LockMode::Exclusive).unwrap();
+        file.lock_range(20, 10, LockMode::Shared).unwrap();
+        assert!(!child_can_lock(&path, 5, 10, LockMode::Shared));
+        assert!(!child_can_lock(&path, 25, 1, LockMode::Exclusive));
+        assert!(child_can_lock(&path, 25, 10, LockMode::Shared));
+        assert!(child_can_lock(&path, 10, 10, LockMode::Exclusive));
+
+        // A zero length extends the lock to the end of the file.
+        file.lock_range(30, 0, LockMode::Exclusive).unwrap();
+        assert!(!child_can_lock(&path, 1 << 40, 1, LockMode::Shared));
+        assert!(child_can_lock(&path, 10, 10, LockMode::Exclusive));
+    }
+
+    /// Tests that adjacent ranges merge and that unlocking part of a range
+    /// splits it.
+    #[test]
+    fn lock_range_split_merge() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        // Adjacent ranges merge, so a single unlock releases both.
+        file.lock_range(0, 10, LockMode::Exclusive).unwrap();
+        file.lock_range(10, 10, LockMode::Exclusive).unwrap();
+        assert!(!child_can_lock(&path, 9, 2, LockMode::Shared));
+        file.unlock_range(0, 20).unwrap();
+        assert!(child_can_lock(&path, 0, 20, LockMode::Exclusive));
+
+        // Unlocking the middle of a range leaves both ends locked.
+        file.lock_range(0, 30, LockMode::Exclusive).unwrap();
+        file.unlock_range(10, 10).unwrap();
+        assert!(child_can_lock(&path, 10, 10, LockMode::Exclusive));
+        assert!(!child_can_lock(&path, 5, 1, LockMode::Shared));
+        assert!(!child_can_lock(&path, 25, 1, LockMode::Shared));
+
+        // Locking part of a range with another mode converts just that part.
+        file.lock_range(0, 10, LockMode::Shared).unwrap();
+        assert!(child_can_lock(&path, 0, 10, LockMode::Shared));
+        assert!(!child_can_lock(&path, 20, 10, LockMode::Shared));
+        file.unlock_range(0, 0).unwrap();
+        assert!(child_can_lock(&path, 0, 0, LockMode::Exclusive));
+    }
+
+    /// Tests that locking a range another process holds fails with the
+    /// contended error, even though POSIX allows `EACCES` as well.
+    #[test]
+    fn lock_range_contended() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        file.lock_range(0, 10, LockMode::Exclusive).unwrap();
+        assert_eq!(in_child(&path, "try_lock_range 0 1 shared"), "contended");
+        assert_eq!(
+            file.try_lock_range(u64::MAX, 1, LockMode::Shared)
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::InvalidInput
+        );
+    }
+
+    /// Tests that OFD locks conflict between separately opened descriptors of
+    /// the same process, but not with duplicates of the locking descriptor.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn ofd_lock_per_descriptor() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        file1.locks(LockBackend::Ofd).lock_exclusive().unwrap();
+        assert_eq!(
+            file2
+                .locks(LockBackend::Ofd)
+                .try_lock_shared()
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        // A duplicate shares the open file description, and so the lock.
+        let dup = file1.try_clone().unwrap();
+        dup.locks(LockBackend::Ofd).lock_shared().unwrap();
+        assert!(child_can_lock(&path, 0, 0, LockMode::Shared));
+        file2.locks(LockBackend::Ofd).try_lock_shared().unwrap();
+
+        // Unlike POSIX record locks, closing another descriptor of the file
+        // does not release the lock.
+        drop(file2);
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        assert_eq!(
+            file2
+                .locks(LockBackend::Ofd)
+                .try_lock_exclusive()
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+
+        // Closing every descriptor of the description does.
+        drop(file1);
+        drop(dup);
+        file2.locks(LockBackend::Ofd).try_lock_exclusive().unwrap();
+    }
+
+    /// Tests that OFD range locks only conflict where the ranges overlap.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn ofd_lock_range() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let (locks1, locks2) = (file1.locks(LockBackend::Ofd), file2.locks(LockBackend::Ofd));
+
+        locks1.lock_range(0, 10, LockMode::Exclusive).unwrap();
+        locks2.lock_range(10, 10, LockMode::Exclusive).unwrap();
+        assert_eq!(
+            locks2
+                .try_lock_range(5, 10, LockMode::Shared)
+                .unwrap_err()
+                .raw_os_error(),
             lock_contended_error().raw_os_error()
         );
-        file1.lock_shared().unwrap();
+        assert!(locks1.try_lock_exclusive().is_err());
+
+        // Unlocking part of a range leaves the rest locked.
+        locks1.unlock_range(5, 5).unwrap();
+        locks2.try_lock_range(5, 5, LockMode::Shared).unwrap();
+        assert!(locks2.try_lock_range(4, 1, LockMode::Shared).is_err());
+
+        locks1.unlock().unwrap();
+        locks2.unlock().unwrap();
+        locks1.try_lock_exclusive().unwrap();
+    }
+
+    /// Tests that every backend reports contention with the same error.
+    #[test]
+    fn lock_backend_contended() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        let mut backends = vec![LockBackend::Flock];
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        backends.push(LockBackend::Ofd);
+        for backend in backends {
+            file1.locks(backend).lock_exclusive().unwrap();
+            assert_eq!(
+                file2
+                    .locks(backend)
+                    .try_lock_shared()
+                    .unwrap_err()
+                    .raw_os_error(),
+                lock_contended_error().raw_os_error()
+            );
+            file1.locks(backend).unlock().unwrap();
+            file2.locks(backend).try_lock_shared().unwrap();
+            file2.locks(backend).unlock().unwrap();
+        }
+
+        // POSIX locks belong to the process, so they only conflict with
+        // another process.
+        let locks = file1.locks(LockBackend::Posix);
+        locks.lock_exclusive().unwrap();
+        file2
+            .locks(LockBackend::Posix)
+            .try_lock_exclusive()
+            .unwrap();
+        assert_eq!(in_child(&path, "try_lock_posix shared"), "contended");
+        locks.unlock().unwrap();
+        assert_eq!(in_child(&path, "try_lock_posix shared"), "locked");
+    }
+
+    /// Tests that the `FileExt` lock methods use the crate-wide default
+    /// backend, which can only be set before it is first used. The default is
+    /// set in a child process so that it does not affect concurrently running
+    /// tests.
+    #[test]
+    fn lock_default_backend() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        fs::File::create(&path).unwrap();
+
+        assert_eq!(crate::default_lock_backend(), LockBackend::Flock);
+        crate::set_default_lock_backend(LockBackend::Flock).unwrap();
+        assert!(crate::set_default_lock_backend(LockBackend::Posix).is_err());
+        assert_eq!(in_child(&path, "default_posix"), "true");
+    }
+
+    /// Tests that the holder of a conflicting lock is reported without
+    /// locking anything.
+    #[test]
+    fn lock_holder() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        file.lock_range(0, 10, LockMode::Exclusive).unwrap();
+        file.lock_range(20, 0, LockMode::Shared).unwrap();
+        // Locks of the calling process are never reported.
+        assert_eq!(file.lock_holder(LockMode::Exclusive, 0, 0).unwrap(), None);
+
+        let parent = std::process::id();
+        assert_eq!(
+            in_child(&path, "lock_holder shared 5 1"),
+            format!("exclusive lock on bytes 0..10 held by pid {}", parent)
+        );
+        assert_eq!(
+            in_child(&path, "lock_holder exclusive 30 1"),
+            format!("shared lock on bytes 20.. held by pid {}", parent)
+        );
+        assert_eq!(in_child(&path, "lock_holder shared 30 1"), "none");
+        assert_eq!(in_child(&path, "lock_holder exclusive 10 10"), "none");
+    }
+
+    /// Tests that whole-file locks taken with the default `flock` backend are
+    /// reported to another process.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn lock_holder_flock() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let parent = std::process::id();
+
+        assert_eq!(in_child(&path, "lock_holder exclusive 0 0"), "none");
+        FileExt::lock_exclusive(&file).unwrap();
+        assert_eq!(file.lock_holder(LockMode::Exclusive, 0, 0).unwrap(), None);
+        assert_eq!(
+            in_child(&path, "lock_holder shared 0 0"),
+            format!("exclusive lock on bytes 0.. held by pid {}", parent)
+        );
+
+        // flock conflicts between `File`s of one process too.
+        let other = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let holder = other.lock_holder(LockMode::Shared, 0, 0).unwrap().unwrap();
+        assert_eq!(
+            (holder.mode(), holder.pid()),
+            (LockMode::Exclusive, Some(parent))
+        );
+
+        // Only the lock held through the queried `File` is skipped.
+        FileExt::lock_shared(&file).unwrap();
+        FileExt::lock_shared(&other).unwrap();
+        let holder = file
+            .lock_holder(LockMode::Exclusive, 0, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(holder.mode(), LockMode::Shared);
+        FileExt::unlock(&other).unwrap();
+        assert_eq!(file.lock_holder(LockMode::Exclusive, 0, 0).unwrap(), None);
+        assert_eq!(in_child(&path, "lock_holder shared 0 0"), "none");
+        assert_eq!(
+            in_child(&path, "lock_holder exclusive 10 5"),
+            format!("shared lock on bytes 0.. held by pid {}", parent)
+        );
+    }
+
+    /// Tests that OFD locks are reported without a PID.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn lock_holder_ofd() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+
+        file1
+            .locks(LockBackend::Ofd)
+            .lock_range(10, 10, LockMode::Shared)
+            .unwrap();
+        let locks = file2.locks(LockBackend::Ofd);
+        assert_eq!(locks.lock_holder(LockMode::Shared, 0, 0).unwrap(), None);
+        let holder = locks
+            .lock_holder(LockMode::Exclusive, 0, 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            (holder.mode(), holder.offset(), holder.len(), holder.pid()),
+            (LockMode::Shared, 10, 10, None)
+        );
+        assert_eq!(holder.to_string(), "shared lock on bytes 10..20");
+        locks.try_lock_range(0, 10, LockMode::Exclusive).unwrap();
+    }
+
+    /// Tests that OFD and POSIX locks are converted atomically, and keep their
+    /// mode when an upgrade fails.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn lock_convert_atomic() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        let (locks1, locks2) = (file1.locks(LockBackend::Ofd), file2.locks(LockBackend::Ofd));
+
+        locks1.lock_shared().unwrap();
+        locks2.lock_shared().unwrap();
+        assert_eq!(
+            locks1.try_upgrade().unwrap_err().raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        assert!(locks2.try_lock_exclusive().is_err());
+
+        locks2.unlock().unwrap();
+        assert_eq!(locks1.upgrade().unwrap(), LockConversion::Atomic);
+        assert!(locks2.try_lock_shared().is_err());
+        assert_eq!(locks1.downgrade().unwrap(), LockConversion::Atomic);
+        locks2.try_lock_shared().unwrap();
+        locks2.unlock().unwrap();
+        locks1.unlock().unwrap();
+
+        // POSIX locks never conflict within the process, so only check that the
+        // conversion is atomic and visible to another process.
+        let locks = file1.locks(LockBackend::Posix);
+        locks.lock_shared().unwrap();
+        assert_eq!(locks.try_upgrade().unwrap(), LockConversion::Atomic);
+        assert!(!child_can_lock(&path, 0, 0, LockMode::Shared));
+        assert_eq!(locks.downgrade().unwrap(), LockConversion::Atomic);
+        assert!(child_can_lock(&path, 0, 0, LockMode::Shared));
+        assert!(!child_can_lock(&path, 0, 0, LockMode::Exclusive));
+    }
+
+    /// Tests that the lock status of a file is reported without taking a lock,
+    /// along with whether the calling process holds the lock.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    #[test]
+    fn lock_status() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let open = || {
+            fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)
+                .unwrap()
+        };
+        let (file1, file2) = (open(), open());
+        assert_eq!(file2.lock_status().unwrap(), LockStatus::Unlocked);
+
+        FileExt::lock_shared(&file1).unwrap();
+        assert_eq!(
+            file2.lock_status().unwrap(),
+            LockStatus::SharedHeld { held_by_self: true }
+        );
+        FileExt::try_lock_shared(&file2).unwrap();
+        FileExt::unlock(&file2).unwrap();
+
+        FileExt::lock_exclusive(&file1).unwrap();
+        assert_eq!(
+            file2.lock_status().unwrap(),
+            LockStatus::ExclusiveHeld { held_by_self: true }
+        );
+        assert_eq!(
+            in_child(&path, "lock_status"),
+            "ExclusiveHeld { held_by_self: false }"
+        );
+        FileExt::unlock(&file1).unwrap();
+        assert!(!file2.lock_status().unwrap().is_locked());
+
+        // Only locks of the queried backend are reported.
+        file1.locks(LockBackend::Posix).lock_shared().unwrap();
+        assert_eq!(file2.lock_status().unwrap(), LockStatus::Unlocked);
+        let status = file2.locks(LockBackend::Posix).lock_status().unwrap();
+        assert!(status.is_held_by_self());
+        assert_eq!(status, LockStatus::SharedHeld { held_by_self: true });
+        file1.locks(LockBackend::Posix).unlock().unwrap();
+
+        // OFD locks are listed without a process, so they are matched against
+        // the locks of the descriptors of the calling process.
+        file1.locks(LockBackend::Ofd).lock_exclusive().unwrap();
+        assert_eq!(
+            file2.locks(LockBackend::Ofd).lock_status().unwrap(),
+            LockStatus::ExclusiveHeld { held_by_self: true }
+        );
+        assert_eq!(
+            in_child(&path, "lock_status ofd"),
+            "ExclusiveHeld { held_by_self: false }"
+        );
+    }
+
+    /// Tests that a blocking lock interrupted by a signal keeps waiting rather
+    /// than fail with `EINTR`. The signal handler is installed for the whole
+    /// process, so the lock is taken in a child process (see `interrupt_lock`).
+    #[test]
+    fn lock_interrupted() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        fs::File::create(&path).unwrap();
+        assert_eq!(in_child(&path, "lock_interrupted"), "locked");
+    }
+
+    /// Locks `file`, then blocks locking `other` on another thread, interrupts
+    /// the wait with a signal, and returns the outcome of that lock once `file`
+    /// is unlocked.
+    fn interrupt_lock(file: fs::File, other: fs::File) -> std::io::Result<()> {
+        use std::os::unix::thread::JoinHandleExt;
+        use std::time::Duration;
+
+        extern "C" fn ignore(_signal: libc::c_int) {}
+
+        // Without `SA_RESTART`, the signal interrupts the blocked lock.
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = ignore as *const () as libc::sighandler_t;
+            libc::sigemptyset(&mut action.sa_mask);
+            assert_eq!(
+                libc::sigaction(libc::SIGUSR2, &action, std::ptr::null_mut()),
+                0
+            );
+        }
+
+        FileExt::lock_exclusive(&file)?;
+        let waiter = std::thread::spawn(move || FileExt::lock_exclusive(&other));
+        std::thread::sleep(Duration::from_millis(50));
+        unsafe { libc::pthread_kill(waiter.as_pthread_t(), libc::SIGUSR2) };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        FileExt::unlock(&file)?;
+        waiter.join().unwrap()
     }
 }
@@ -8,6 +8,54 @@ macro_rules! lock_impl {
             lock_file(file, LOCKFILE_EXCLUSIVE_LOCK)
         }
 
+        pub fn lock_range(file: &$file, offset: u64, len: u64, mode: crate::LockMode) -> Result<()> {
+            crate::windows::lock_file(
+                file.as_raw_handle() as HANDLE,
+                crate::windows::mode_flags(mode),
+                offset,
+                len,
+            )
+        }
+
+        pub fn upgrade(file: &$file) -> Result<crate::LockConversion> {
+            crate::windows::upgrade(file.as_raw_handle() as HANDLE, true)
+        }
+
+        pub fn fs_stats(file: &$file) -> Result<crate::FsStats> {
+            crate::windows::fstatvfs(file.as_raw_handle() as HANDLE)
+        }
+
+        lock_impl!(@try $file);
+    };
+    ($file: ty, $spawn_blocking: path, $sleep: path) => {
+        pub async fn lock_shared(file: &$file) -> Result<()> {
+            crate::windows::lock_async(file.as_raw_handle() as HANDLE, 0, $sleep).await
+        }
+
+        pub async fn lock_exclusive(file: &$file) -> Result<()> {
+            crate::windows::lock_async(
+                file.as_raw_handle() as HANDLE,
+                LOCKFILE_EXCLUSIVE_LOCK,
+                $sleep,
+            )
+            .await
+        }
+
+        pub async fn upgrade(file: &$file) -> Result<crate::LockConversion> {
+            crate::windows::upgrade_async(file.as_raw_handle() as HANDLE, $sleep).await
+        }
+
+        pub async fn fs_stats(file: &$file) -> Result<crate::FsStats> {
+            // The volume may be slow to answer, so it is queried on a duplicate
+            // of the handle.
+            let handle = unsafe { std::os::windows::io::BorrowedHandle::borrow_raw(file.as_raw_handle()) }
+                .try_clone_to_owned()?;
+            $spawn_blocking(move || crate::windows::fstatvfs(handle.as_raw_handle() as HANDLE)).await
+        }
+
+        lock_impl!(@try $file);
+    };
+    (@try $file: ty) => {
         pub fn try_lock_shared(file: &$file) -> Result<()> {
             lock_file(file, LOCKFILE_FAIL_IMMEDIATELY)
         }
@@ -17,33 +65,60 @@ macro_rules! lock_impl {
         }
 
         pub fn unlock(file: &$file) -> Result<()> {
-            unsafe {
-                let ret = UnlockFile(file.as_raw_handle() as HANDLE, 0, 0, !0, !0);
-                if ret == 0 {
-                    Err(Error::last_os_error())
-                } else {
-                    Ok(())
-                }
-            }
+            crate::windows::unlock_file(file.as_raw_handle() as HANDLE, 0, 0)
+        }
+
+        pub fn try_lock_range(
+            file: &$file,
+            offset: u64,
+            len: u64,
+            mode: crate::LockMode,
+        ) -> Result<()> {
+            crate::windows::lock_file(
+                file.as_raw_handle() as HANDLE,
+                crate::windows::mode_flags(mode) | LOCKFILE_FAIL_IMMEDIATELY,
+                offset,
+                len,
+            )
+        }
+
+        pub fn unlock_range(file: &$file, offset: u64, len: u64) -> Result<()> {
+            crate::windows::unlock_file(file.as_raw_handle() as HANDLE, offset, len)
+        }
+
+        pub fn try_upgrade(file: &$file) -> Result<crate::LockConversion> {
+            crate::windows::upgrade(file.as_raw_handle() as HANDLE, false)
+        }
+
+        pub fn downgrade(file: &$file) -> Result<crate::LockConversion> {
+            crate::windows::downgrade(file.as_raw_handle() as HANDLE)
+        }
+
+        pub fn lock_holder(
+            _file: &$file,
+            _mode: crate::LockMode,
+            _offset: u64,
+            _len: u64,
+        ) -> Result<Option<crate::LockHolder>> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "lock holders cannot be queried on Windows",
+            ))
+        }
+
+        pub fn lock_status(_file: &$file) -> Result<crate::LockStatus> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "lock status cannot be queried on Windows",
+            ))
+        }
+
+        pub fn file_id(file: &$file) -> Result<(u64, u64)> {
+            crate::windows::file_id(file.as_raw_handle() as HANDLE)
         }
 
         fn lock_file(file: &$file, flags: u32) -> Result<()> {
-            unsafe {
-                let mut overlapped = mem::zeroed();
-                let ret = LockFileEx(
-                    file.as_raw_handle() as HANDLE,
-                    flags,
-                    0,
-                    !0,
-                    !0,
-                    &mut overlapped,
-                );
-                if ret == 0 {
-                    Err(Error::last_os_error())
-                } else {
-                    Ok(())
-                }
-            }
+            crate::windows::lock_file(file.as_raw_handle() as HANDLE, flags, 0, 0)
         }
     };
 }
@@ -53,17 +128,156 @@ pub(crate) mod async_impl;
 #[cfg(feature = "sync")]
 pub(crate) mod sync_impl;
 
-use crate::FsStats;
+use crate::{FsStats, FsType, LockConversion, LockMode};
 use std::io::{Error, Result};
-use std::os::windows::ffi::OsStrExt;
+use std::mem;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
 use std::path::Path;
-use windows_sys::Win32::Foundation::ERROR_LOCK_VIOLATION;
-use windows_sys::Win32::Storage::FileSystem::{GetDiskFreeSpaceW, GetVolumePathNameW};
+use windows_sys::Win32::Foundation::{ERROR_LOCK_VIOLATION, ERROR_POSSIBLE_DEADLOCK, HANDLE};
+use windows_sys::Win32::Storage::FileSystem::{
+    GetDiskFreeSpaceW, GetVolumeInformationW, GetVolumePathNameW, LockFileEx, UnlockFile,
+    LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+};
+use windows_sys::Win32::System::IO::OVERLAPPED;
 
 pub fn lock_error() -> Error {
     Error::from_raw_os_error(ERROR_LOCK_VIOLATION as i32)
 }
 
+pub(crate) fn deadlock_error() -> Error {
+    Error::from_raw_os_error(ERROR_POSSIBLE_DEADLOCK as i32)
+}
+
+pub(crate) fn mode_flags(mode: LockMode) -> u32 {
+    match mode {
+        LockMode::Shared => 0,
+        LockMode::Exclusive => LOCKFILE_EXCLUSIVE_LOCK,
+    }
+}
+
+/// Returns the length of a range starting at `offset`, where a `len` of zero
+/// extends it to the end of the largest possible file.
+fn range_len(offset: u64, len: u64) -> u64 {
+    if len == 0 {
+        u64::MAX - offset
+    } else {
+        len
+    }
+}
+
+/// Locks `len` bytes starting at `offset`, or everything from `offset` on if
+/// `len` is zero.
+pub(crate) fn lock_file(handle: HANDLE, flags: u32, offset: u64, len: u64) -> Result<()> {
+    let len = range_len(offset, len);
+    unsafe {
+        let mut overlapped: OVERLAPPED = mem::zeroed();
+        overlapped.Anonymous.Anonymous.Offset = offset as u32;
+        overlapped.Anonymous.Anonymous.OffsetHigh = (offset >> 32) as u32;
+        let ret = LockFileEx(
+            handle,
+            flags,
+            0,
+            len as u32,
+            (len >> 32) as u32,
+            &mut overlapped,
+        );
+        if ret == 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Unlocks a range previously locked with the same `offset` and `len`.
+pub(crate) fn unlock_file(handle: HANDLE, offset: u64, len: u64) -> Result<()> {
+    let len = range_len(offset, len);
+    unsafe {
+        let ret = UnlockFile(
+            handle,
+            offset as u32,
+            (offset >> 32) as u32,
+            len as u32,
+            (len >> 32) as u32,
+        );
+        if ret == 0 {
+            Err(Error::last_os_error())
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Converts the whole-file shared lock held through `handle` to an exclusive
+/// one. `LockFileEx` cannot convert locks, so the shared lock is released first
+/// and taken again without waiting if the exclusive lock cannot be acquired.
+pub(crate) fn upgrade(handle: HANDLE, wait: bool) -> Result<LockConversion> {
+    unlock_file(handle, 0, 0)?;
+    let flags = if wait {
+        LOCKFILE_EXCLUSIVE_LOCK
+    } else {
+        LOCKFILE_EXCLUSIVE_LOCK | LOCKFILE_FAIL_IMMEDIATELY
+    };
+    match lock_file(handle, flags, 0, 0) {
+        Ok(()) => Ok(LockConversion::Reacquired),
+        Err(e) => Err(crate::lock_conversion::restore(
+            lock_file(handle, LOCKFILE_FAIL_IMMEDIATELY, 0, 0),
+            e,
+        )),
+    }
+}
+
+/// Converts the whole-file exclusive lock held through `handle` to a shared
+/// one. A handle may layer a shared lock over its own exclusive lock, and
+/// unlocking the range releases the exclusive lock first, so this is atomic.
+pub(crate) fn downgrade(handle: HANDLE) -> Result<LockConversion> {
+    lock_file(handle, LOCKFILE_FAIL_IMMEDIATELY, 0, 0)?;
+    unlock_file(handle, 0, 0)?;
+    Ok(LockConversion::Atomic)
+}
+
+/// Locks the whole file without blocking the executor of an async runtime,
+/// polling with an [`ExponentialBackoff`](crate::ExponentialBackoff) policy
+/// while the file is contended. No thread is kept waiting in `LockFileEx`, so
+/// dropping the returned future leaves the file as it was.
+#[cfg(any(feature = "smol", feature = "async-std", feature = "tokio"))]
+pub(crate) async fn lock_async<Z, Fut>(handle: HANDLE, flags: u32, sleep: Z) -> Result<()>
+where
+    Z: Fn(std::time::Duration) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let mut policy = crate::ExponentialBackoff::default();
+    crate::retry::lock_until_async(
+        None,
+        &mut policy,
+        || lock_file(handle, flags | LOCKFILE_FAIL_IMMEDIATELY, 0, 0),
+        sleep,
+    )
+    .await
+}
+
+/// Converts the whole-file shared lock held through `handle` to an exclusive
+/// one like `upgrade`, polling like `lock_async`. Every attempt releases the
+/// shared lock, and takes it again if it fails, so if the returned future is
+/// dropped, the file is left locked shared, but another process may lock the
+/// file between attempts.
+#[cfg(any(feature = "smol", feature = "async-std", feature = "tokio"))]
+pub(crate) async fn upgrade_async<Z, Fut>(handle: HANDLE, sleep: Z) -> Result<LockConversion>
+where
+    Z: Fn(std::time::Duration) -> Fut,
+    Fut: std::future::Future<Output = ()>,
+{
+    let mut policy = crate::ExponentialBackoff::default();
+    crate::retry::lock_until_async(
+        None,
+        &mut policy,
+        || upgrade(handle, false).map(|_| ()),
+        sleep,
+    )
+    .await?;
+    Ok(LockConversion::Reacquired)
+}
+
 fn volume_path(path: &Path, volume_path: &mut [u16]) -> Result<()> {
     let path_utf8: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
     unsafe {
@@ -80,6 +294,58 @@ fn volume_path(path: &Path, volume_path: &mut [u16]) -> Result<()> {
     }
 }
 
+/// Returns an ID which is the same for every open file referring to the same
+/// file on disk.
+pub(crate) fn file_id(handle: HANDLE) -> Result<(u64, u64)> {
+    use windows_sys::Win32::Storage::FileSystem::{
+        GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+    };
+
+    unsafe {
+        let mut info: BY_HANDLE_FILE_INFORMATION = mem::zeroed();
+        if GetFileInformationByHandle(handle, &mut info) == 0 {
+            return Err(Error::last_os_error());
+        }
+        Ok((
+            info.dwVolumeSerialNumber as u64,
+            (info.nFileIndexHigh as u64) << 32 | info.nFileIndexLow as u64,
+        ))
+    }
+}
+
+/// Returns whether a process with ID `pid` exists, whether or not the calling
+/// process may query it.
+#[cfg(feature = "sync")]
+pub(crate) fn process_exists(pid: u32) -> Result<bool> {
+    use windows_sys::Win32::Foundation::{
+        CloseHandle, ERROR_ACCESS_DENIED, ERROR_INVALID_PARAMETER, STILL_ACTIVE,
+    };
+    use windows_sys::Win32::System::Threading::{
+        GetExitCodeProcess, OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION,
+    };
+
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid);
+        if process == 0 {
+            let err = Error::last_os_error();
+            return match err.raw_os_error().map(|code| code as u32) {
+                Some(ERROR_ACCESS_DENIED) => Ok(true),
+                Some(ERROR_INVALID_PARAMETER) => Ok(false),
+                _ => Err(err),
+            };
+        }
+        let mut exit_code = 0;
+        let ret = GetExitCodeProcess(process, &mut exit_code);
+        let err = Error::last_os_error();
+        CloseHandle(process);
+        if ret == 0 {
+            Err(err)
+        } else {
+            Ok(exit_code == STILL_ACTIVE as u32)
+        }
+    }
+}
+
 pub fn statvfs(path: &Path) -> Result<FsStats> {
     let root_path: &mut [u16] = &mut [0; 261];
     volume_path(path, root_path)?;
@@ -101,12 +367,97 @@ pub fn statvfs(path: &Path) -> Result<FsStats> {
             let bytes_per_cluster = sectors_per_cluster as u64 * bytes_per_sector as u64;
             let free_space = bytes_per_cluster * number_of_free_clusters as u64;
             let total_space = bytes_per_cluster * total_number_of_clusters as u64;
+            // The space functions only need `GetDiskFreeSpaceW`, so they don't
+            // fail on volumes whose information can't be read.
+            let volume = volume_info(root_path).unwrap_or(VolumeInfo {
+                fs_type: FsType::Other,
+                serial_number: 0,
+                max_name_len: 0,
+                read_only: false,
+            });
             Ok(FsStats {
                 free_space,
                 available_space: free_space,
                 total_space,
                 allocation_granularity: bytes_per_cluster,
+                fs_type: volume.fs_type,
+                total_inodes: None,
+                free_inodes: None,
+                available_inodes: None,
+                max_name_len: volume.max_name_len,
+                fsid: volume.serial_number,
+                block_size: bytes_per_cluster,
+                read_only: volume.read_only,
+                nosuid: false,
             })
         }
     }
 }
+
+/// Returns the stats of the file system holding the file open as `handle`,
+/// found through the path the file is currently at.
+pub(crate) fn fstatvfs(handle: HANDLE) -> Result<FsStats> {
+    use windows_sys::Win32::Storage::FileSystem::GetFinalPathNameByHandleW;
+
+    let mut path = vec![0u16; 261];
+    loop {
+        let len =
+            unsafe { GetFinalPathNameByHandleW(handle, path.as_mut_ptr(), path.len() as u32, 0) };
+        match len as usize {
+            0 => return Err(Error::last_os_error()),
+            // The buffer was too small, and `len` is the size needed.
+            len if len > path.len() => path.resize(len, 0),
+            len => {
+                path.truncate(len);
+                break;
+            }
+        }
+    }
+    statvfs(Path::new(&std::ffi::OsString::from_wide(&path)))
+}
+
+/// Returns the type of the file system containing `path`, from the name of the
+/// file system of its volume.
+pub fn filesystem_type(path: &Path) -> Result<FsType> {
+    let root_path: &mut [u16] = &mut [0; 261];
+    volume_path(path, root_path)?;
+    volume_info(root_path).map(|volume| volume.fs_type)
+}
+
+struct VolumeInfo {
+    fs_type: FsType,
+    serial_number: u64,
+    max_name_len: u64,
+    read_only: bool,
+}
+
+fn volume_info(root_path: &[u16]) -> Result<VolumeInfo> {
+    use windows_sys::Win32::System::SystemServices::FILE_READ_ONLY_VOLUME;
+
+    let mut serial_number = 0;
+    let mut max_name_len = 0;
+    let mut flags = 0;
+    let mut name = [0u16; 261];
+    let ret = unsafe {
+        GetVolumeInformationW(
+            root_path.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            &mut serial_number,
+            &mut max_name_len,
+            &mut flags,
+            name.as_mut_ptr(),
+            name.len() as u32,
+        )
+    };
+    if ret == 0 {
+        return Err(Error::last_os_error());
+    }
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Ok(VolumeInfo {
+        fs_type: FsType::from_name(&String::from_utf16_lossy(&name[..len])),
+        serial_number: serial_number as u64,
+        max_name_len: max_name_len as u64,
+        read_only: flags & FILE_READ_ONLY_VOLUME != 0,
+    })
+}
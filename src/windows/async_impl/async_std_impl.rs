@@ -5,13 +5,13 @@ use std::os::windows::io::AsRawHandle;
 use windows_sys::Win32::Foundation::HANDLE;
 use windows_sys::Win32::Storage::FileSystem::{
     FileAllocationInfo, FileStandardInfo, GetFileInformationByHandleEx,
-    SetFileInformationByHandle, UnlockFile, FILE_ALLOCATION_INFO,
-    FILE_STANDARD_INFO, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY, LockFileEx,
+    SetFileInformationByHandle, FILE_ALLOCATION_INFO,
+    FILE_STANDARD_INFO, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
 };
 
 use async_std::fs::File;
 
-lock_impl!(File);
+lock_impl!(File, crate::blocking::spawn_async_std, crate::retry::sleep_async_std);
 allocate!(File);
 allocate_size!(File);
 
@@ -33,13 +33,13 @@ mod test {
         let file = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
 
         // Multiple exclusive locks fails.
-        file.lock_exclusive().unwrap();
+        file.lock_exclusive().await.unwrap();
         assert_eq!(file.try_lock_exclusive().unwrap_err().raw_os_error(),
                    lock_contended_error().raw_os_error());
         file.unlock().unwrap();
 
         // Shared then Exclusive locks fails.
-        file.lock_shared().unwrap();
+        file.lock_shared().await.unwrap();
         assert_eq!(file.try_lock_exclusive().unwrap_err().raw_os_error(),
                    lock_contended_error().raw_os_error());
     }
@@ -53,9 +53,9 @@ mod test {
         let file = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
 
         // Open two shared locks on the file, and then try and fail to open an exclusive lock.
-        file.lock_exclusive().unwrap();
-        file.lock_shared().unwrap();
-        file.lock_shared().unwrap();
+        file.lock_exclusive().await.unwrap();
+        file.lock_shared().await.unwrap();
+        file.lock_shared().await.unwrap();
         assert_eq!(file.try_lock_exclusive().unwrap_err().raw_os_error(),
                    lock_contended_error().raw_os_error());
 
@@ -71,7 +71,7 @@ mod test {
 
         // Pop the exclusive lock and finally succeed.
         file.unlock().unwrap();
-        file.lock_exclusive().unwrap();
+        file.lock_exclusive().await.unwrap();
     }
 
     /// A file handle with multiple open locks will have all locks closed on drop.
@@ -83,11 +83,11 @@ mod test {
         let file2 = fs::OpenOptions::new().read(true).write(true).create(true).open(&path).await.unwrap();
 
         // Open two shared locks on the file, and then try and fail to open an exclusive lock.
-        file1.lock_shared().unwrap();
+        file1.lock_shared().await.unwrap();
         assert_eq!(file2.try_lock_exclusive().unwrap_err().raw_os_error(),
                    lock_contended_error().raw_os_error());
 
         drop(file1);
-        file2.lock_exclusive().unwrap();
+        file2.lock_exclusive().await.unwrap();
     }
 }
\ No newline at end of file
@@ -5,9 +5,8 @@ use std::os::windows::io::AsRawHandle;
 
 use windows_sys::Win32::Foundation::HANDLE;
 use windows_sys::Win32::Storage::FileSystem::{
-    FileAllocationInfo, FileStandardInfo, GetFileInformationByHandleEx, LockFileEx,
-    SetFileInformationByHandle, UnlockFile, FILE_ALLOCATION_INFO, FILE_STANDARD_INFO,
-    LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
+    FileAllocationInfo, FileStandardInfo, GetFileInformationByHandleEx, SetFileInformationByHandle,
+    FILE_ALLOCATION_INFO, FILE_STANDARD_INFO, LOCKFILE_EXCLUSIVE_LOCK, LOCKFILE_FAIL_IMMEDIATELY,
 };
 
 lock_impl!(File);
@@ -61,7 +60,7 @@ mod test {
 
     use std::fs;
 
-    use crate::{lock_contended_error, FileExt};
+    use crate::{lock_contended_error, FileExt, LockMode};
 
     /// A file handle may not be exclusively locked multiple times, or exclusively locked and then
     /// shared locked.
@@ -73,21 +72,26 @@ mod test {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .unwrap();
 
         // Multiple exclusive locks fails.
-        file.lock_exclusive().unwrap();
+        FileExt::lock_exclusive(&file).unwrap();
         assert_eq!(
-            file.try_lock_exclusive().unwrap_err().raw_os_error(),
+            FileExt::try_lock_exclusive(&file)
+                .unwrap_err()
+                .raw_os_error(),
             lock_contended_error().raw_os_error()
         );
-        file.unlock().unwrap();
+        FileExt::unlock(&file).unwrap();
 
         // Shared then Exclusive locks fails.
-        file.lock_shared().unwrap();
+        FileExt::lock_shared(&file).unwrap();
         assert_eq!(
-            file.try_lock_exclusive().unwrap_err().raw_os_error(),
+            FileExt::try_lock_exclusive(&file)
+                .unwrap_err()
+                .raw_os_error(),
             lock_contended_error().raw_os_error()
         );
     }
@@ -102,35 +106,42 @@ mod test {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .unwrap();
 
         // Open two shared locks on the file, and then try and fail to open an exclusive lock.
-        file.lock_exclusive().unwrap();
-        file.lock_shared().unwrap();
-        file.lock_shared().unwrap();
+        FileExt::lock_exclusive(&file).unwrap();
+        FileExt::lock_shared(&file).unwrap();
+        FileExt::lock_shared(&file).unwrap();
         assert_eq!(
-            file.try_lock_exclusive().unwrap_err().raw_os_error(),
+            FileExt::try_lock_exclusive(&file)
+                .unwrap_err()
+                .raw_os_error(),
             lock_contended_error().raw_os_error()
         );
 
         // Pop one of the shared locks and try again.
-        file.unlock().unwrap();
+        FileExt::unlock(&file).unwrap();
         assert_eq!(
-            file.try_lock_exclusive().unwrap_err().raw_os_error(),
+            FileExt::try_lock_exclusive(&file)
+                .unwrap_err()
+                .raw_os_error(),
             lock_contended_error().raw_os_error()
         );
 
         // Pop the second shared lock and try again.
-        file.unlock().unwrap();
+        FileExt::unlock(&file).unwrap();
         assert_eq!(
-            file.try_lock_exclusive().unwrap_err().raw_os_error(),
+            FileExt::try_lock_exclusive(&file)
+                .unwrap_err()
+                .raw_os_error(),
             lock_contended_error().raw_os_error()
         );
 
         // Pop the exclusive lock and finally succeed.
-        file.unlock().unwrap();
-        file.lock_exclusive().unwrap();
+        FileExt::unlock(&file).unwrap();
+        FileExt::lock_exclusive(&file).unwrap();
     }
 
     /// A file handle with multiple open locks will have all locks closed on drop.
@@ -142,23 +153,101 @@ mod test {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .unwrap();
         let file2 = fs::OpenOptions::new()
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(&path)
             .unwrap();
 
         // Open two shared locks on the file, and then try and fail to open an exclusive lock.
-        file1.lock_shared().unwrap();
+        FileExt::lock_shared(&file1).unwrap();
         assert_eq!(
-            file2.try_lock_exclusive().unwrap_err().raw_os_error(),
+            FileExt::try_lock_exclusive(&file2)
+                .unwrap_err()
+                .raw_os_error(),
             lock_contended_error().raw_os_error()
         );
 
         drop(file1);
-        file2.lock_exclusive().unwrap();
+        FileExt::lock_exclusive(&file2).unwrap();
+    }
+
+    /// Range locks conflict between handles only where the ranges overlap, and
+    /// must be unlocked with the exact range they were locked with.
+    #[test]
+    fn lock_range() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        file1.lock_range(0, 10, LockMode::Exclusive).unwrap();
+        assert_eq!(
+            file2
+                .try_lock_range(5, 10, LockMode::Shared)
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        file2.try_lock_range(10, 10, LockMode::Exclusive).unwrap();
+
+        // Unlocking a range that was not locked as a whole fails.
+        assert!(file1.unlock_range(0, 5).is_err());
+        file1.unlock_range(0, 10).unwrap();
+        file2.try_lock_range(0, 10, LockMode::Shared).unwrap();
+    }
+
+    /// A zero length locks everything from a nonzero offset to the end of the
+    /// file, and unlocks the same range.
+    #[test]
+    fn lock_range_to_end() {
+        let tempdir = tempdir::TempDir::new("fs4").unwrap();
+        let path = tempdir.path().join("fs4");
+        let file1 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+        let file2 = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .unwrap();
+
+        file1.lock_range(10, 0, LockMode::Exclusive).unwrap();
+        assert_eq!(
+            file2
+                .try_lock_range(u64::MAX - 1, 1, LockMode::Shared)
+                .unwrap_err()
+                .raw_os_error(),
+            lock_contended_error().raw_os_error()
+        );
+        file2.try_lock_range(0, 10, LockMode::Exclusive).unwrap();
+
+        file1.unlock_range(10, 0).unwrap();
+        file2
+            .try_lock_range(u64::MAX - 1, 1, LockMode::Exclusive)
+            .unwrap();
     }
 }